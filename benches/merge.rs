@@ -0,0 +1,71 @@
+//! Check merge and log renaming time grows linearly by contacts count.
+//!
+//! cargo bench --bench merge [-- <CONTACTS>]
+
+use std::env;
+use std::hint::black_box;
+use std::io::Cursor;
+use std::time::Instant;
+
+use vcf2msip::ini_io::IniIo;
+use vcf2msip::xml_parser::SipContacts;
+
+const DEFAULT_CONTACTS: usize = 20_000;
+
+fn number(i: usize) -> String {
+    format!("03-{:04}-{:04}", i / 10000, i % 10000)
+}
+
+/// Merge existing Contacts.xml by vcf numbers
+fn merge(contacts: usize) -> f64 {
+    let mut xml = String::from("<?xml version=\"1.0\"?>\r\n<contacts>\r\n");
+    for i in 0..contacts {
+        xml.push_str(&format!(
+            "<contact name=\"Name {}\" number=\"{}\" firstname=\"\" lastname=\"\" presence=\"0\" directory=\"0\"/>\r\n",
+            i, number(i)
+        ));
+    }
+    xml.push_str("</contacts>\r\n");
+    let start = Instant::now();
+    let mut sip_contacts = SipContacts::from_reader(Cursor::new(xml));
+    for i in (0..contacts).step_by(2) {
+        sip_contacts.clear_exist(&number(i));
+    }
+    black_box(sip_contacts.data().len());
+    start.elapsed().as_secs_f64()
+}
+
+/// Rename call logs by vcf numbers, 5 calls per contact
+fn renew_logs(contacts: usize) -> f64 {
+    let mut text = String::from("[Calls]\r\n");
+    for i in 0..contacts * 5 {
+        text.push_str(&format!("{}={};Old;2;{};0;cancel\r\n", i, number(i % contacts), i));
+    }
+    let start = Instant::now();
+    let mut ini_io = IniIo::from_text(&text);
+    let mut renamed = 0;
+    for i in 0..contacts {
+        renamed += ini_io.rename_calls(&number(i), "New").len();
+    }
+    black_box(renamed);
+    start.elapsed().as_secs_f64()
+}
+
+fn bench(title: &str, contacts: usize, f: impl Fn(usize) -> f64) {
+    let sec = f(contacts);
+    let sec2 = f(contacts * 2);
+    println!(
+        "{:<12} {:>8} contacts {:>8.3} sec / {:>8} contacts {:>8.3} sec ({:.1} x)",
+        title, contacts, sec, contacts * 2, sec2, sec2 / sec
+    );
+}
+
+fn main() {
+    let contacts = env::args()
+        .skip(1)
+        .find_map(|a| a.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_CONTACTS);
+    println!("Linear if about 2.0 x");
+    bench("merge", contacts, merge);
+    bench("renew logs", contacts, renew_logs);
+}
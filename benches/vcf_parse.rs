@@ -0,0 +1,93 @@
+//! Compare whole file parsing (regex compiled per field) with streaming parsing.
+//!
+//! cargo bench --bench vcf_parse [-- <CARDS>]
+
+use std::env;
+use std::fs::{self, File};
+use std::hint::black_box;
+use std::io::{BufReader, Read, Write};
+use std::time::Instant;
+
+use regex::Regex;
+use vcf2msip::vcf_parser::{Contact, Vcf};
+
+const DEFAULT_CARDS: usize = 5_000;
+
+/// Make large vcf file like Google contacts export
+fn make_vcf_file(filename: &str, cards: usize) {
+    let mut hfile = File::create(filename).unwrap();
+    for i in 0..cards {
+        write!(
+            hfile,
+            "BEGIN:VCARD\r\nVERSION:3.0\r\nFN:Taro Yamada {i}\r\nN:Yamada;Taro {i};;;\r\n\
+            X-PHONETIC-FIRST-NAME:ﾀﾛｳ\r\nX-PHONETIC-LAST-NAME:ﾔﾏﾀﾞ\r\n\
+            TEL;TYPE=CELL:090-{i:04}-1111\r\nTEL;TYPE=WORK:03-{i:04}-2222\r\n\
+            item1.TEL:06-{i:04}-3333\r\nitem1.X-ABLabel:Office\r\n\
+            item2.ORG:CORP {i}\r\nCATEGORIES:myContacts\r\nEND:VCARD\r\n"
+        ).unwrap();
+    }
+}
+
+/// Parsing before streaming: read all, split, compile regex for each field
+#[allow(clippy::regex_creation_in_loops)] // it is what is measured
+fn legacy_parse(filename: &str) -> usize {
+    let mut data = String::with_capacity(1048576);
+    BufReader::new(File::open(filename).unwrap()).read_to_string(&mut data).unwrap();
+    let mut block: Vec<&str> = data.split("END:VCARD").collect();
+    block.pop();
+    let capture = |prefix: &str, vcard: &str| {
+        let re = Regex::new(&format!("(?m)^{}(.*)$", prefix)).unwrap();
+        re.captures(vcard).map_or(0, |c| c[1].len())
+    };
+    let mut count = 0;
+    for vcard in block {
+        for prefix in ["FN:", "N:", "X-PHONETIC-FIRST-NAME:", "X-PHONETIC-LAST-NAME:", "CATEGORIES:", r"item[\d]*.ORG:", "X-MICROSIP-SPEEDDIAL:"] {
+            count += capture(prefix, vcard);
+        }
+        let re = Regex::new(r"(?m)^(item\d)*[\.]*TEL(;TYPE=([a-zA-Z]*))*:(.*)$").unwrap();
+        for cap in re.captures_iter(vcard) {
+            if let Some(item) = cap.get(1) {
+                let label = Regex::new(&format!(r"(?m)^{}.X-ABLabel:(.*)$", item.as_str())).unwrap();
+                count += label.captures(vcard).map_or(0, |c| c[1].len());
+            }
+            count += 1;
+        }
+    }
+    count
+}
+
+/// Streaming parsing
+fn streaming_parse(filename: &str) -> usize {
+    let vcf = Vcf::new(filename).unwrap();
+    let mut count = 0;
+    for vcard in vcf.reader().unwrap() {
+        let ct = Contact::new(&vcard.unwrap());
+        count += ct.tel_iter().count();
+    }
+    count
+}
+
+fn bench(title: &str, f: impl Fn() -> usize) -> f64 {
+    let start = Instant::now();
+    black_box(f());
+    let sec = start.elapsed().as_secs_f64();
+    println!("{:<12} {:>8.3} sec", title, sec);
+    sec
+}
+
+fn main() {
+    let cards = env::args()
+        .skip(1)
+        .find_map(|a| a.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_CARDS);
+    let filename = env::temp_dir().join("vcf2msip_bench.vcf");
+    let filename = filename.to_str().unwrap();
+    make_vcf_file(filename, cards);
+
+    println!("vcf_parse: {} cards", cards);
+    let legacy = bench("legacy", || legacy_parse(filename));
+    let streaming = bench("streaming", || streaming_parse(filename));
+    println!("{:<12} {:>8.1} x", "speed up", legacy / streaming);
+
+    fs::remove_file(filename).unwrap();
+}
@@ -1,23 +1,111 @@
+use crate::error_flg;
 use crate::file_fns;
+use crate::sort_order;
+use crate::vcf_check;
+use crate::logger;
+use crate::msip_dir;
+use crate::ldap_source;
+use crate::csv_source;
+use crate::phonebook_source;
+use crate::watch;
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use clap::builder::{PossibleValuesParser, RangedU64ValueParser, StyledStr, TypedValueParser};
+use clap::error::ErrorKind;
+use clap_complete::Shell;
+use error_flg::*;
 use file_fns::*;
+use csv_source::{CsvOptions, CSV_ENCODINGS, CSV_HEADERS, is_valid_map};
+use ldap_source::{LdapMapping, LDAP_FILTER_DEFAULT, LDAP_MAP_DEFAULT};
+use logger::LogLevel;
+use msip_dir::{find_microsip_dir, MicrosipDir, ENV_MICROSIP_DIR};
+use phonebook_source::*;
+use watch::WATCH_DEBOUNCE_DEFAULT;
+use sort_order::{SortOrder, SORT_KEYS};
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::{Path, MAIN_SEPARATOR_STR};
+use vcf_check::CHECK_MAX_WIDTH_DEFAULT;
 
-pub const ARG_HELP: &'static [&'static str] = &["-h", "-v", "--help", "--version"];
-pub const ARG_MERGE: &'static [&'static str] = &["-m", "--merge"];
-pub const ARG_OVERWRITE: &'static [&'static str] = &["-n", "--no-bup"];
-pub const ARG_RENEWLOGS: &'static [&'static str] = &["-r", "--renew-logs"];
-pub const ARG_OUTPUT_CSV_AGEPHONE: &'static [&'static str] = &["-ca", "--output-csv-agephone"];
+pub const ENV_CARDDAV_PASSWORD: &str = "VCF2MSIP_CARDDAV_PASSWORD";
+pub const ENV_CARDDAV_TOKEN: &str = "VCF2MSIP_CARDDAV_TOKEN";
+pub const ENV_LDAP_PASSWORD: &str = "VCF2MSIP_LDAP_PASSWORD";
 
-pub const ARG_PAT_NAME: &'static str = "%name%";
-pub const ARG_PAT_FIRST_INITIAL: &'static str = "%finitial%";
-pub const ARG_PAT_LAST_INITIAL: &'static str = "%linitial%";
-pub const ARG_PAT_TEL_TYPE: &'static str = "%teltype%";
-pub const ARG_PAT_CATEGORIES: &'static str = "%categories%";
-pub const ARG_PAT_DEFAULT: &'static str = "%linitial% - %name% (%teltype%)";
+pub const ARG_QUIET: &[&str] = &["-q", "--quiet"];
+pub const ARG_VERBOSE: &[&str] = &["-v", "--verbose"];
+pub const ARG_MERGE: &[&str] = &["-m", "--merge"];
+pub const ARG_OVERWRITE: &[&str] = &["-n", "--no-bup"];
+pub const ARG_RENEWLOGS: &[&str] = &["-r", "--renew-logs"];
+pub const ARG_OUTPUT_CSV_AGEPHONE: &[&str] = &["-ca", "--output-csv-agephone"];
+pub const ARG_SORT: &[&str] = &["-s", "--sort"];
+pub const ARG_INI_FILE: &[&str] = &["--ini-file"];
+pub const ARG_SPEED_DIAL: &[&str] = &["-sd", "--speed-dial"];
+pub const ARG_SPEED_DIAL_CATEGORY: &[&str] = &["--speed-dial-category"];
+pub const SPEED_DIAL_CATEGORY_DEFAULT: &str = "SpeedDial";
+pub const ARG_KEEP_BACKUPS: &[&str] = &["--keep-backups"];
+pub const ARG_WAIT: &[&str] = &["--wait"];
+pub const ARG_FORCE: &[&str] = &["-f", "--force"];
+pub const ARG_NUMBER: &[&str] = &["-N", "--number"];
+pub const ARG_FIND_MICROSIP: &[&str] = &["--find-microsip"];
+pub const ARG_CARDDAV: &[&str] = &["--carddav"];
+pub const ARG_CARDDAV_USER: &[&str] = &["--carddav-user"];
+pub const ARG_LDAP: &[&str] = &["--ldap"];
+pub const ARG_LDAP_BASE: &[&str] = &["--ldap-base"];
+pub const ARG_LDAP_FILTER: &[&str] = &["--ldap-filter"];
+pub const ARG_LDAP_BIND: &[&str] = &["--ldap-bind"];
+pub const ARG_LDAP_MAP: &[&str] = &["--ldap-map"];
+pub const ARG_CSV_MAP: &[&str] = &["--csv-map"];
+pub const ARG_CSV_ENCODING: &[&str] = &["--csv-encoding"];
+pub const ARG_CSV_HEADER: &[&str] = &["--csv-header"];
+pub const ARG_INPUT_FORMAT: &[&str] = &["--input-format"];
+pub const ARG_WATCH: &[&str] = &["--watch"];
+pub const ARG_INTERACTIVE: &[&str] = &["-i", "--interactive"];
+pub const ARG_WATCH_XML: &[&str] = &["--watch-xml"];
+pub const ARG_WATCH_DEBOUNCE: &[&str] = &["--watch-debounce"];
 
-const _PKG_VERSION: &'static str = env!("CARGO_PKG_VERSION");
-const _PKG_NAME: &'static str = env!("CARGO_PKG_NAME");
-const _PKG_AUTHORS: &'static str = env!("CARGO_PKG_AUTHORS");
-const _PKG_DESCRIPTION: &'static str = env!("CARGO_PKG_DESCRIPTION");
+pub const ARG_REPORT: &[&str] = &["--report"];
+pub const ARG_REPORT_FILE: &[&str] = &["--report-file"];
+pub const ARG_PHOTO_DIR: &[&str] = &["--photo-dir"];
+pub const ARG_DIAL_FORMAT: &[&str] = &["--dial-format"];
+pub const REPORT_FORMAT_JSON: &str = "json";
+
+pub const ARG_JSON: &[&str] = &["--json"];
+pub const ARG_MAX_WIDTH: &[&str] = &["--max-width"];
+
+/// Old options of two letters, replaced by long name before parsing
+const LEGACY_SHORTS: &[&[&str]] = &[ARG_OUTPUT_CSV_AGEPHONE, ARG_SPEED_DIAL];
+
+pub const CMD_CONVERT: &str = "convert";
+pub const CMD_MERGE: &str = "merge";
+pub const CMD_RENEW_LOGS: &str = "renew-logs";
+pub const CMD_EXPORT_CSV: &str = "export-csv";
+pub const CMD_INI: &str = "ini";
+pub const CMD_CHECK: &str = "check";
+pub const CMD_BACKUPS: &str = "backups";
+pub const CMD_COMPLETIONS: &str = "completions";
+pub const CMD_MAN: &str = "man";
+pub const INI_DEFAULT_FILE: &str = "MicroSIP.ini";
+pub const CONTACTS_XML_FILE: &str = "Contacts.xml";
+
+pub const ARG_PAT_NAME: &str = "%name%";
+pub const ARG_PAT_FIRST_INITIAL: &str = "%finitial%";
+pub const ARG_PAT_LAST_INITIAL: &str = "%linitial%";
+pub const ARG_PAT_TEL_TYPE: &str = "%teltype%";
+pub const ARG_PAT_CATEGORIES: &str = "%categories%";
+pub const ARG_PAT_DEFAULT: &str = "%linitial% - %name% (%teltype%)";
+pub const ARG_PAT_NUMBER: &str = "%number%";
+pub const ARG_PAT_EXT: &str = "%ext%";
+pub const DIAL_FORMAT_DEFAULT: &str = "%number%,,%ext%";
+
+const CSV_MAP_FIELDS: &str = "name | reading | company | category | last | first | tel.TYPE";
+const LDAP_MAP_FIELDS: &str = "name | last | first | phonetic-last | phonetic-first | org | categories | tel.TYPE";
+
+const _PKG_VERSION: &str = env!("CARGO_PKG_VERSION");
+const _PKG_NAME: &str = env!("CARGO_PKG_NAME");
+const _PKG_AUTHORS: &str = env!("CARGO_PKG_AUTHORS");
+const _PKG_DESCRIPTION: &str = env!("CARGO_PKG_DESCRIPTION");
+
+/// Kind and message of invalid args, found after parsing
+type ArgError = (ErrorKind, String);
 
 #[derive(Debug, Default)]
 pub struct Args {
@@ -25,122 +113,713 @@ pub struct Args {
     save_file_name: String,
     microsip_ini_file: String,
     name_pattern_normal: String,
-    is_help: bool,
     is_merge: bool,
     is_no_bup: bool,
     is_renew_logs: bool,
-    is_output_csv_agephone: bool
+    is_output_csv_agephone: bool,
+    sort_order: SortOrder,
+    is_speed_dial: bool,
+    speed_dial_category: String,
+    subcommand: String,
+    sub_args: Vec<String>,
+    is_json: bool,
+    check_max_width: usize,
+    report_file_name: String,
+    photo_dir: String,
+    dial_format: String,
+    log_level: LogLevel,
+    keep_backups: usize,
+    wait_secs: u64,
+    is_force: bool,
+    is_find_microsip: bool,
+    carddav_url: String,
+    carddav_user: String,
+    ldap_url: String,
+    ldap_base: String,
+    ldap_filter: String,
+    ldap_bind: String,
+    ldap_mapping: LdapMapping,
+    csv_options: CsvOptions,
+    input_format: String,
+    is_watch: bool,
+    is_interactive: bool,
+    is_watch_xml: bool,
+    watch_debounce: u64,
+    shell: Option<Shell>,
+}
+
+/// Make arg of ARG_* names, id is long name without "--"
+fn named_arg(names: &[&'static str], help: impl Into<StyledStr>) -> Arg {
+    let long = names.iter().find_map(|n| n.strip_prefix("--")).unwrap_or_default();
+    let short = names.iter().filter_map(|n| n.strip_prefix('-')).find(|n| n.len() == 1);
+    Arg::new(long).long(long).short(short.and_then(|s| s.chars().next())).help(help.into())
+}
+
+fn flag(names: &[&'static str], help: impl Into<StyledStr>) -> Arg {
+    named_arg(names, help).action(ArgAction::SetTrue)
+}
+
+fn option(names: &[&'static str], value_name: &'static str, help: impl Into<StyledStr>) -> Arg {
+    named_arg(names, help).value_name(value_name).action(ArgAction::Set)
+}
+
+fn positive() -> RangedU64ValueParser<usize> {
+    RangedU64ValueParser::new().range(1..)
+}
+
+fn non_empty(value: &str) -> Result<String, String> {
+    if value.is_empty() {
+        return Err("must not be empty".to_string());
+    }
+    Ok(value.to_string())
+}
+
+fn dial_format(value: &str) -> Result<String, String> {
+    if !value.contains(ARG_PAT_NUMBER) {
+        return Err(format!("must have {}", ARG_PAT_NUMBER));
+    }
+    Ok(value.to_string())
+}
+
+fn csv_map(value: &str) -> Result<String, String> {
+    if !is_valid_map(value) {
+        return Err(format!("must be FIELD=COLUMN,... of FIELD: {}", CSV_MAP_FIELDS));
+    }
+    Ok(value.to_string())
+}
+
+fn ldap_map(value: &str) -> Result<LdapMapping, String> {
+    LdapMapping::parse(value).ok_or(format!("must be FIELD=ATTR,... of FIELD: {}", LDAP_MAP_FIELDS))
+}
+
+/// Options of backup and MicroSIP running, for commands writing files
+fn write_args() -> [Arg; 3] {
+    [
+        flag(ARG_OVERWRITE, "Do not create backup. Default: create backup."),
+        option(ARG_WAIT, "SECONDS", "Wait MicroSIP to be closed. Default: 0, stop if running.")
+            .value_parser(value_parser!(u64)),
+        flag(ARG_FORCE, "Write even if MicroSIP is running."),
+    ]
+}
+
+/// Args of convert, merge, renew-logs and export-csv subcommands
+fn convert_args() -> Vec<Arg> {
+    let mut args = vec![
+        Arg::new("input").value_name("VCF").required_unless_present("find-microsip")
+            .help(format!("Load file, *.vcf, *.csv or *.xml. See {}.", ARG_INPUT_FORMAT[0])),
+        Arg::new("output").value_name("CONTACTS_XML")
+            .help(format!("Save file, any name. Default: Contacts.xml in found MicroSIP data directory. See {}.", ARG_FIND_MICROSIP[0])),
+        Arg::new("pattern").value_name("PATTERN")
+            .help(format!("Pattern of name, has '%'. Default: \"{}\"", ARG_PAT_DEFAULT)),
+        flag(ARG_MERGE, "Merge from exist MicroSIP contacts too. Default: no merge."),
+        flag(ARG_RENEWLOGS, "Renew name in logs tab. Default: no touch."),
+        flag(ARG_OUTPUT_CSV_AGEPHONE, "Just convert to CSV for AGEphone. Same as export-csv."),
+        flag(ARG_SPEED_DIAL, "Write speed dial buttons to MicroSIP.ini. Default: no touch."),
+        option(ARG_SPEED_DIAL_CATEGORY, "NAME", "Category of speed dial and BLF contacts.")
+            .default_value(SPEED_DIAL_CATEGORY_DEFAULT),
+        option(ARG_SORT, "KEY", "Sort output by KEY. Default: vcf order, then merged.")
+            .value_parser(PossibleValuesParser::new(SORT_KEYS).map(|s| SortOrder::from_arg(&s).unwrap_or_default()))
+            .ignore_case(true),
+        option(ARG_KEEP_BACKUPS, "N", "Delete backups except last N runs, after success. Default: keep all.")
+            .value_parser(positive()),
+        option(ARG_REPORT, "FORMAT", "Print json report to stdout.")
+            .value_parser([REPORT_FORMAT_JSON]),
+        option(ARG_REPORT_FILE, "FILE", "Write json report to FILE.").value_parser(non_empty),
+        option(ARG_DIAL_FORMAT, "FORMAT", format!("Number of extension or DTMF, by {} and {}.", ARG_PAT_NUMBER, ARG_PAT_EXT))
+            .value_parser(dial_format).default_value(DIAL_FORMAT_DEFAULT),
+        option(ARG_PHOTO_DIR, "DIR", "Write photos of vcf to DIR as CARD_NUMBER.jpg, referenced only by json report.")
+            .value_parser(non_empty),
+        flag(ARG_INTERACTIVE, "Review contacts before writing: search, change name, turn off number."),
+        flag(ARG_WATCH, "Keep running, convert again when input file is changed.")
+            .conflicts_with_all(["carddav", "ldap", "interactive"]),
+        flag(ARG_WATCH_XML, format!("{}, and convert again when MicroSIP changed save file too.", ARG_WATCH[0]))
+            .conflicts_with_all(["carddav", "ldap", "interactive"]),
+        option(ARG_WATCH_DEBOUNCE, "SECONDS", format!("Convert after files are not changed for SECONDS. Default: {}.", WATCH_DEBOUNCE_DEFAULT))
+            .value_parser(value_parser!(u64)),
+        option(ARG_INI_FILE, "FILE", format!("MicroSIP.ini for {} and {}. Default: beside save file.", ARG_RENEWLOGS[1], ARG_SPEED_DIAL[1])),
+        option(ARG_CARDDAV, "URL", "Fetch vcf from CardDAV address book to vcf file first, only changes from last time.")
+            .value_parser(non_empty).conflicts_with("ldap"),
+        option(ARG_CARDDAV_USER, "USER", format!("User of CardDAV basic auth, password is {}. Or bearer token is {}.", ENV_CARDDAV_PASSWORD, ENV_CARDDAV_TOKEN)),
+        option(ARG_INPUT_FORMAT, "FORMAT", "Format of load file, auto is by extension. *.csv of AGEphone is found. xml: phonebook of Yealink, Cisco or Grandstream desk phone.")
+            .value_parser(INPUT_FORMATS.to_vec()).default_value(INPUT_FORMAT_AUTO),
+        option(ARG_CSV_MAP, "FIELD=COLUMN,...", format!("Columns of *.csv input, by header name or number from 1. Default: found by header. FIELD: {}", CSV_MAP_FIELDS))
+            .value_parser(csv_map),
+        option(ARG_CSV_ENCODING, "ENCODING", "Encoding of *.csv input, auto is UTF-8 or Shift_JIS.")
+            .value_parser(CSV_ENCODINGS.to_vec()).default_value(CSV_ENCODINGS[0]),
+        option(ARG_CSV_HEADER, "HEADER", "Header of *.csv input, auto is no header if first row has a number.")
+            .value_parser(CSV_HEADERS.to_vec()).default_value(CSV_HEADERS[0]),
+        option(ARG_LDAP, "URL", "Search LDAP directory (ldap:// or ldaps://) to vcf file first.")
+            .value_parser(non_empty),
+        option(ARG_LDAP_BASE, "DN", "Base DN of search."),
+        option(ARG_LDAP_FILTER, "FILTER", "Filter of search.").default_value(LDAP_FILTER_DEFAULT),
+        option(ARG_LDAP_BIND, "DN", format!("Bind DN, password is {}. Default: anonymous.", ENV_LDAP_PASSWORD)),
+        option(ARG_LDAP_MAP, "FIELD=ATTR,...", format!("Change attribute of field. \"FIELD=\" removes field. Default: \"{}\" FIELD: {}", LDAP_MAP_DEFAULT, LDAP_MAP_FIELDS))
+            .value_parser(ldap_map),
+        flag(ARG_FIND_MICROSIP, format!("List MicroSIP data directories, found by {}, beside this exe, %APPDATA% and Wine.", ENV_MICROSIP_DIR)),
+    ];
+    args.extend(write_args());
+    args
+}
+
+/// Command of convert, and its presets
+fn convert_command(name: &'static str, about: &'static str) -> Command {
+    let excluded: &[&str] = match name {
+        CMD_MERGE => &["merge"],
+        CMD_RENEW_LOGS => &["renew-logs"],
+        CMD_EXPORT_CSV => &["output-csv-agephone", "merge", "renew-logs", "speed-dial", "speed-dial-category", "ini-file"],
+        _ => &[],
+    };
+    let args = convert_args().into_iter()
+        .filter(|a| !excluded.contains(&a.get_id().as_str()))
+        .map(|a| match a.get_id().as_str() {
+            "output" if name == CMD_EXPORT_CSV => a.value_name("CSV").help("Save file of CSV."),
+            _ => a,
+        });
+    Command::new(name).about(about).args_override_self(true).args(args)
+        .after_help(format!("\
+PATTERN of name, from vcf contact:
+  {}\t... Full name or Organization name.
+  {}\t... Initial of first name or %name%.
+  {}\t... Initial of last name or %name%.
+  {}\t... Telephone type.
+  {}\t... Categories string.
+Emptied () and [] are removed at last.
+Messages and progress are printed to stderr, progress only to terminal.",
+            ARG_PAT_NAME, ARG_PAT_FIRST_INITIAL, ARG_PAT_LAST_INITIAL, ARG_PAT_TEL_TYPE, ARG_PAT_CATEGORIES,
+        ))
+}
+
+/// Return definition of command line
+pub fn command() -> Command {
+    let xml_arg = || Arg::new("xml").value_name("CONTACTS_XML").help("Default: Contacts.xml in found MicroSIP data directory.");
+    let key_arg = || Arg::new("key").value_name("SECTION.KEY").required(true);
+    Command::new(_PKG_NAME)
+        .version(_PKG_VERSION)
+        .about(format!("{}\nby {}", _PKG_DESCRIPTION, _PKG_AUTHORS))
+        .subcommand_required(true)
+        .arg_required_else_help(true)
+        .args_override_self(true)
+        .after_help(format!("Old form without subcommand is same as {}: {} [OPTIONS] <VCF> [CONTACTS_XML] [PATTERN]", CMD_CONVERT, _PKG_NAME))
+        .arg(flag(ARG_QUIET, "Errors only.").global(true).conflicts_with("verbose"))
+        .arg(flag(ARG_VERBOSE, "Also print skipped cards and backups.").global(true))
+        .subcommand(convert_command(CMD_CONVERT, "Convert vcf to MicroSIP contacts."))
+        .subcommand(convert_command(CMD_MERGE, "Convert, and merge from exist MicroSIP contacts too. Same as convert --merge."))
+        .subcommand(convert_command(CMD_RENEW_LOGS, "Convert, and renew name in logs tab. Same as convert --renew-logs."))
+        .subcommand(convert_command(CMD_EXPORT_CSV, "Just convert to CSV for AGEphone. Same as convert --output-csv-agephone."))
+        .subcommand(Command::new(CMD_CHECK)
+            .about("Report problematic cards without writing. Exit with error if any error is found.")
+            .args_override_self(true)
+            .arg(Arg::new("input").value_name("VCF").required(true))
+            .arg(Arg::new("pattern").value_name("PATTERN").help(format!("Default: \"{}\"", ARG_PAT_DEFAULT)))
+            .arg(flag(ARG_JSON, "Report in json."))
+            .arg(option(ARG_MAX_WIDTH, "N", format!("Max columns of name. Default: {}.", CHECK_MAX_WIDTH_DEFAULT))
+                .value_parser(positive())))
+        .subcommand(Command::new(CMD_BACKUPS)
+            .about("List or restore backups. Backups of Contacts.xml and MicroSIP.ini written by one run have same number.")
+            .subcommand_required(true)
+            .args_override_self(true)
+            .args(write_args().map(|a| a.global(true)))
+            .subcommand(Command::new("list").about("List backups with time and contacts count.").arg(xml_arg()))
+            .subcommand(Command::new("restore")
+                .about(format!("Restore both files from backup N, or last one. Current files are backed up unless {}.", ARG_OVERWRITE[1]))
+                .arg(option(ARG_NUMBER, "N", "Number of backup to restore. Default: last one.").value_parser(value_parser!(u32)))
+                .arg(xml_arg())))
+        .subcommand(Command::new(CMD_INI)
+            .about(format!("Get or set MicroSIP.ini. Default: \"{}\" in found MicroSIP data directory.", INI_DEFAULT_FILE))
+            .subcommand_required(true)
+            .args_override_self(true)
+            .args(write_args().map(|a| a.global(true)))
+            .arg(option(ARG_INI_FILE, "FILE", "MicroSIP.ini to get or set.").global(true))
+            .subcommand(Command::new("get").about("Print value.").arg(key_arg()))
+            .subcommand(Command::new("set")
+                .about(format!("Set values, with backup unless {}.", ARG_OVERWRITE[1]))
+                .arg(Arg::new("values").value_names(["SECTION.KEY", "VALUE"]).num_args(2..).required(true).allow_negative_numbers(true)))
+            .subcommand(Command::new("unset").about("Remove key.").arg(key_arg()))
+            .subcommand(Command::new("list").about("List accounts.")))
+        .subcommand(Command::new(CMD_COMPLETIONS)
+            .about("Print shell completion script.")
+            .arg(Arg::new("shell").value_name("SHELL").required(true).value_parser(value_parser!(Shell))))
+        .subcommand(Command::new(CMD_MAN).about("Print man page."))
+}
+
+/// Return args of current form, from old form without subcommand
+///
+/// Old two letter options are replaced by long name, and "convert" is inserted.
+/// Values of options and args after "--" are not replaced.
+fn compat_args<T: Into<OsString>>(args: impl IntoIterator<Item = T>) -> Vec<OsString> {
+    let mut args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+    let cmd = command();
+    let value_options = value_option_names(&cmd);
+    let mut is_value = false;
+    for arg in args.iter_mut().skip(1) {
+        if arg == "--" {
+            break;
+        }
+        if !is_value {
+            if let Some(names) = LEGACY_SHORTS.iter().find(|names| *arg == names[0]) {
+                *arg = names[1].into();
+            }
+        }
+        is_value = !is_value && value_options.iter().any(|o| arg == o.as_str());
+    }
+    let globals = [ARG_QUIET, ARG_VERBOSE].concat();
+    let first = args.iter().skip(1).position(|a| !globals.iter().any(|g| a == g)).map(|i| i + 1);
+    let is_current = first.is_none_or(|i| {
+        let arg = args[i].to_str().unwrap_or_default();
+        ["-h", "--help", "-V", "--version", "help"].contains(&arg)
+            || cmd.get_subcommands().any(|c| c.get_name() == arg)
+    });
+    if !is_current {
+        args.insert(1, CMD_CONVERT.into());
+    }
+    args
+}
+
+/// Return "--long" and "-s" names of options taking a value, in command and its subcommands
+fn value_option_names(cmd: &Command) -> Vec<String> {
+    let mut names: Vec<String> = cmd.get_arguments()
+        .filter(|a| !a.is_positional() && a.get_action().takes_values())
+        .flat_map(|a| [a.get_long().map(|l| format!("--{}", l)), a.get_short().map(|s| format!("-{}", s))])
+        .flatten()
+        .collect();
+    for sub in cmd.get_subcommands() {
+        names.extend(value_option_names(sub));
+    }
+    names
+}
+
+/// Return string value of arg, empty if not given or not defined in subcommand
+fn text(m: &ArgMatches, id: &str) -> String {
+    m.try_get_one::<String>(id).ok().flatten().cloned().unwrap_or_default()
+}
+
+fn texts(m: &ArgMatches, id: &str) -> Vec<String> {
+    m.try_get_many::<String>(id).ok().flatten().map_or(Vec::new(), |v| v.cloned().collect())
+}
+
+fn is_on(m: &ArgMatches, id: &str) -> bool {
+    m.try_get_one::<bool>(id).ok().flatten().is_some_and(|b| *b)
+}
+
+fn native_path(path: &str) -> String {
+    path.replace('/', MAIN_SEPARATOR_STR)
 }
 
 impl Args {
 
     /// Set structure from console args
+    ///
+    /// Exit with message if args are invalid, or help and version.
+    /// MicroSIP data directory is found after parsing, only if files are not given.
     pub fn get_params() -> Self {
-        let mut args = Args::default();
-        let mut usrtxt_count = 0;
-        let ms = MAIN_SEPARATOR.to_string();
-        args.name_pattern_normal = ARG_PAT_DEFAULT.to_string();
-        for (i, arg) in env::args().enumerate() {
-            if i == 0 { continue; }
-            if ARG_HELP.contains(&arg.as_ref()) { args.is_help = true; break; }
-            else if ARG_MERGE.contains(&arg.as_ref()) { args.is_merge = true; }
-            else if ARG_OVERWRITE.contains(&arg.as_ref()) { args.is_no_bup = true; }
-            else if ARG_RENEWLOGS.contains(&arg.as_ref()) { args.is_renew_logs = true; }
-            else if ARG_OUTPUT_CSV_AGEPHONE.contains(&arg.as_ref()) { args.is_output_csv_agephone = true; }
-            else {
-                match usrtxt_count {
-                    0 => args.load_file_name = arg.replace("/", &ms),
-                    1 => args.save_file_name = arg.replace("/", &ms),
-                    2 => args.name_pattern_normal = arg,
-                    _ => { args.is_help = true; break; },
-                }
-                usrtxt_count += 1;
+        let mut args = Self::from_args(env::args_os()).unwrap_or_else(|e| e.exit());
+        if args.needs_microsip_dir() {
+            args.resolve_microsip_dir(find_microsip_dir().as_ref());
+        }
+        args
+    }
+
+    /// Set structure from args, first one is program name
+    pub fn from_args<T: Into<OsString>>(args: impl IntoIterator<Item = T>) -> Result<Self, clap::Error> {
+        let mut cmd = command();
+        let matches = cmd.try_get_matches_from_mut(compat_args(args))?;
+        let Some((name, m)) = matches.subcommand() else {
+            unreachable!("subcommand is required");
+        };
+        let res = match name {
+            CMD_INI => Self::from_ini_matches(m),
+            CMD_CHECK => Self::from_check_matches(m),
+            CMD_BACKUPS => Self::from_backups_matches(m),
+            CMD_COMPLETIONS | CMD_MAN => Ok(Args {
+                shell: m.try_get_one::<Shell>("shell").ok().flatten().copied(),
+                ..Default::default()
+            }),
+            _ => Self::from_convert_matches(name, m),
+        };
+        let mut args = match res {
+            Ok(args) => args,
+            Err((kind, message)) => {
+                let sub = cmd.find_subcommand_mut(name).expect("name is of matched subcommand");
+                return Err(sub.error(kind, message));
+            },
+        };
+        args.subcommand = name.to_string();
+        args.log_level = match (is_on(m, "quiet"), is_on(m, "verbose")) {
+            (true, _) => LogLevel::Quiet,
+            (_, true) => LogLevel::Verbose,
+            _ => LogLevel::Normal,
+        };
+        args.resolve_microsip_dir(None);
+        Ok(args)
+    }
+
+    /// Check command reads or writes MicroSIP files
+    fn is_microsip_command(&self) -> bool {
+        match self.subcommand.as_str() {
+            CMD_CHECK | CMD_COMPLETIONS | CMD_MAN => false,
+            CMD_INI | CMD_BACKUPS => true,
+            _ => !self.is_find_microsip && !self.is_output_csv_agephone,
+        }
+    }
+
+    /// Check MicroSIP files are not given, to find MicroSIP data directory
+    fn needs_microsip_dir(&self) -> bool {
+        let filename = if self.subcommand == CMD_INI { &self.microsip_ini_file } else { &self.save_file_name };
+        self.is_microsip_command() && filename.is_empty()
+    }
+
+    /// Fill MicroSIP files not given by args, same order for all commands
+    ///
+    /// 1. Given file, MicroSIP.ini is beside given Contacts.xml
+    /// 2. Files in found MicroSIP data directory
+    pub fn resolve_microsip_dir(&mut self, dir: Option<&MicrosipDir>) {
+        if !self.is_microsip_command() {
+            return;
+        }
+        if self.subcommand == CMD_INI {
+            if self.microsip_ini_file.is_empty() {
+                self.microsip_ini_file = dir.map(|d| d.microsip_ini()).unwrap_or_default();
             }
+            return;
+        }
+        if self.save_file_name.is_empty() {
+            self.save_file_name = dir.map(|d| d.contacts_xml()).unwrap_or_default();
         }
-        #[cfg(debug_assertions)] {
-            // Debug
-            // cargo run -- -m -r -n .\sandbox\contacts.vcf .\sandbox\Contacts.xml
-            // or ...
-            // args.is_help = false;
-            // usrtxt_count = 4;
-            // args.load_file_name = r".\sandbox\contacts.vcf".to_string();
-            // args.save_file_name = r".\sandbox\Contacts.xml".to_string();
-            // args.is_merge = true;
-            // args.is_no_bup = true;
-            // args.is_renew_logs = true;
-            // args.is_output_csv_agephone = false;
-            // args.name_pattern_normal = r"%linitial% - %name% (%teltype%)".to_string();
-        }
-
-        // Out of file and name pattern arg count
-        if usrtxt_count < 2 || usrtxt_count > 4 {
-            args.is_help = true;
+        if self.microsip_ini_file.is_empty() && !self.save_file_name.is_empty() {
+            self.microsip_ini_file = same_dir_filename(&self.save_file_name, INI_DEFAULT_FILE);
+        }
+    }
+
+    /// Set options of commands writing files
+    fn set_write_options(&mut self, m: &ArgMatches) {
+        self.is_no_bup = is_on(m, "no-bup");
+        self.wait_secs = m.try_get_one::<u64>("wait").ok().flatten().copied().unwrap_or(0);
+        self.is_force = is_on(m, "force");
+    }
+
+    /// Set structure from matches of convert, merge, renew-logs and export-csv subcommands
+    fn from_convert_matches(name: &str, m: &ArgMatches) -> Result<Self, ArgError> {
+        let mut args = Args {
+            load_file_name: native_path(&text(m, "input")),
+            save_file_name: native_path(&text(m, "output")),
+            microsip_ini_file: native_path(&text(m, "ini-file")),
+            name_pattern_normal: m.get_one::<String>("pattern").map_or(ARG_PAT_DEFAULT.to_string(), |p| p.clone()),
+            is_merge: name == CMD_MERGE || is_on(m, "merge"),
+            is_renew_logs: name == CMD_RENEW_LOGS || is_on(m, "renew-logs"),
+            is_output_csv_agephone: name == CMD_EXPORT_CSV || is_on(m, "output-csv-agephone"),
+            sort_order: m.get_one::<SortOrder>("sort").copied().unwrap_or_default(),
+            is_speed_dial: is_on(m, "speed-dial"),
+            speed_dial_category: text(m, "speed-dial-category"),
+            is_json: m.get_one::<String>("report").is_some() || m.get_one::<String>("report-file").is_some(),
+            report_file_name: native_path(&text(m, "report-file")),
+            photo_dir: native_path(&text(m, "photo-dir")),
+            dial_format: text(m, "dial-format"),
+            keep_backups: m.get_one::<usize>("keep-backups").copied().unwrap_or(0),
+            is_find_microsip: is_on(m, "find-microsip"),
+            carddav_url: text(m, "carddav"),
+            carddav_user: text(m, "carddav-user"),
+            ldap_url: text(m, "ldap"),
+            ldap_base: text(m, "ldap-base"),
+            ldap_filter: text(m, "ldap-filter"),
+            ldap_bind: text(m, "ldap-bind"),
+            ldap_mapping: m.get_one::<LdapMapping>("ldap-map").cloned().unwrap_or_default(),
+            csv_options: CsvOptions {
+                map: text(m, "csv-map"),
+                encoding: text(m, "csv-encoding"),
+                header: text(m, "csv-header"),
+            },
+            input_format: text(m, "input-format"),
+            is_watch: is_on(m, "watch") || is_on(m, "watch-xml"),
+            is_interactive: is_on(m, "interactive"),
+            is_watch_xml: is_on(m, "watch-xml"),
+            watch_debounce: m.get_one::<u64>("watch-debounce").copied().unwrap_or(WATCH_DEBOUNCE_DEFAULT),
+            ..Default::default()
+        };
+        args.set_write_options(m);
+        if args.is_find_microsip {
+            return Ok(args);
+        }
+        // vcf, [save file], [pattern], pattern has '%'
+        if args.save_file_name.contains('%') {
+            if let Some(pattern) = m.get_one::<String>("pattern") {
+                return Err((ErrorKind::UnknownArgument, format!("unexpected argument '{}', pattern is already given", pattern)));
+            }
+            args.name_pattern_normal = text(m, "output");
+            args.save_file_name.clear();
+        }
+        args.check_convert()?;
+        Ok(args)
+    }
+
+    /// Check combination of convert options, which clap can not check
+    fn check_convert(&self) -> Result<(), ArgError> {
+        // Contact sources write vcf file
+        let is_source = !self.carddav_url.is_empty() || !self.ldap_url.is_empty();
+        if is_source && self.input_format() != INPUT_FORMAT_VCF {
+            return Err((ErrorKind::ArgumentConflict, format!(
+                "'{}' and '{}' write vcf file, input must be vcf", ARG_CARDDAV[0], ARG_LDAP[0]
+            )));
         }
         // Check file name
-        if let Some(s) = Path::new(&args.load_file_name).extension() {
+        if let (INPUT_FORMAT_AUTO, Some(s)) = (self.input_format.as_str(), Path::new(&self.load_file_name).extension()) {
             let ext = s.to_str().map_or("", |s| s);
-            if ext.to_lowercase().as_str() != "vcf" {
-                args.is_help = true;
+            if !["vcf", "csv", "xml"].contains(&ext.to_lowercase().as_str()) {
+                return Err((ErrorKind::InvalidValue, format!(
+                    "load file must be *.vcf, *.csv or *.xml, or give '{}'", ARG_INPUT_FORMAT[0]
+                )));
             }
         }
-        if args.is_output_csv_agephone {
-            args.is_merge = false;
-            args.is_renew_logs = false;
-            args.microsip_ini_file = "".to_string();
-        } else {
-            // Check file name
-            if let Some(s) = Path::new(&args.save_file_name).file_name() {
-                let fname = s.to_str().map_or("", |s| s);
-                if fname.to_lowercase().as_str() != "contacts.xml" {
-                    args.is_help = true;
-                } else {
-                    // make MicroSIP.ini path to same as xml path
-                    if let Some(s) = Path::new(&args.save_file_name).parent() {
-                        let path = s.to_str().map_or("", |s| s);
-                        args.microsip_ini_file = format!("{}{}MicroSIP.ini", path, MAIN_SEPARATOR);
-                    }
-                }
+        if self.is_output_csv_agephone {
+            let csv_option = ARG_OUTPUT_CSV_AGEPHONE[1];
+            let conflicts = [
+                (self.is_merge, ARG_MERGE[1]),
+                (self.is_renew_logs, ARG_RENEWLOGS[1]),
+                (self.is_speed_dial, ARG_SPEED_DIAL[1]),
+                (!self.microsip_ini_file.is_empty(), ARG_INI_FILE[0]),
+            ];
+            if let Some((_, option)) = conflicts.iter().find(|(is_on, _)| *is_on) {
+                return Err((ErrorKind::ArgumentConflict, format!(
+                    "the argument '{}' cannot be used with '{}'", option, csv_option
+                )));
+            }
+            if self.save_file_name.is_empty() {
+                return Err((ErrorKind::MissingRequiredArgument, format!(
+                    "save file is required with '{}'", csv_option
+                )));
             }
         }
-        #[cfg(debug_assertions)] { println!("{:?}", args); }
-        args
+        Ok(())
+    }
+
+    /// Set structure from matches of "ini" subcommand
+    fn from_ini_matches(m: &ArgMatches) -> Result<Self, ArgError> {
+        let Some((action, am)) = m.subcommand() else {
+            unreachable!("action is required");
+        };
+        let mut args = Args {
+            microsip_ini_file: native_path(&text(m, "ini-file")),
+            sub_args: [vec![action.to_string()], texts(am, "key"), texts(am, "values")].concat(),
+            ..Default::default()
+        };
+        args.set_write_options(m);
+        if action == "set" && !args.sub_args[1..].len().is_multiple_of(2) {
+            return Err((ErrorKind::WrongNumberOfValues, "set needs pairs of SECTION.KEY and VALUE".to_string()));
+        }
+        Ok(args)
+    }
+
+    /// Set structure from matches of "check" subcommand
+    fn from_check_matches(m: &ArgMatches) -> Result<Self, ArgError> {
+        Ok(Args {
+            load_file_name: native_path(&text(m, "input")),
+            name_pattern_normal: m.get_one::<String>("pattern").map_or(ARG_PAT_DEFAULT.to_string(), |p| p.clone()),
+            is_json: is_on(m, "json"),
+            check_max_width: m.get_one::<usize>("max-width").copied().unwrap_or(CHECK_MAX_WIDTH_DEFAULT),
+            ..Default::default()
+        })
+    }
+
+    /// Set structure from matches of "backups" subcommand
+    fn from_backups_matches(m: &ArgMatches) -> Result<Self, ArgError> {
+        let Some((action, am)) = m.subcommand() else {
+            unreachable!("action is required");
+        };
+        let number = am.try_get_one::<u32>("number").ok().flatten().map(|n| n.to_string());
+        let mut args = Args {
+            sub_args: [Some(action.to_string()), number].into_iter().flatten().collect(),
+            ..Default::default()
+        };
+        args.set_write_options(m);
+        // Path of Contacts.xml, or found one later
+        let xml = text(am, "xml");
+        if !xml.is_empty() {
+            // Old form "restore N" is not taken as path
+            if xml.chars().all(|c| c.is_ascii_digit()) && !Path::new(&xml).exists() {
+                return Err((ErrorKind::InvalidValue, format!("invalid value '{}' for CONTACTS_XML, give N by {}", xml, ARG_NUMBER[1])));
+            }
+            args.save_file_name = native_path(&xml);
+        }
+        Ok(args)
     }
 
     pub fn load_file_name(&self) -> &str { self.load_file_name.as_ref() }
     pub fn save_file_name(&self) -> &str { self.save_file_name.as_ref() }
     pub fn microsip_ini_file(&self) -> &str { self.microsip_ini_file.as_ref() }
     pub fn name_pattern_normal(&self) -> &str { self.name_pattern_normal.as_ref() }
-    pub fn is_help(&self) -> bool { self.is_help }
     pub fn is_merge(&self) -> bool { self.is_merge }
     pub fn is_no_bup(&self) -> bool { self.is_no_bup }
     pub fn is_renew_logs(&self) -> bool { self.is_renew_logs }
     pub fn is_output_csv_agephone(&self) -> bool { self.is_output_csv_agephone }
+    pub fn sort_order(&self) -> SortOrder { self.sort_order }
+    pub fn is_speed_dial(&self) -> bool { self.is_speed_dial }
+    pub fn speed_dial_category(&self) -> &str { self.speed_dial_category.as_ref() }
+    pub fn is_ini_command(&self) -> bool { self.subcommand == CMD_INI }
+    pub fn sub_args(&self) -> &[String] { self.sub_args.as_ref() }
+    pub fn is_check_command(&self) -> bool { self.subcommand == CMD_CHECK }
+    pub fn is_json(&self) -> bool { self.is_json }
+    pub fn check_max_width(&self) -> usize { self.check_max_width }
+    pub fn report_file_name(&self) -> &str { self.report_file_name.as_ref() }
+    pub fn photo_dir(&self) -> &str { self.photo_dir.as_ref() }
+    pub fn dial_format(&self) -> &str { self.dial_format.as_ref() }
+    pub fn log_level(&self) -> LogLevel { self.log_level }
+    pub fn is_backups_command(&self) -> bool { self.subcommand == CMD_BACKUPS }
+    pub fn is_completions_command(&self) -> bool { self.subcommand == CMD_COMPLETIONS }
+    pub fn is_man_command(&self) -> bool { self.subcommand == CMD_MAN }
+    pub fn keep_backups(&self) -> usize { self.keep_backups }
+    pub fn wait_secs(&self) -> u64 { self.wait_secs }
+    pub fn is_force(&self) -> bool { self.is_force }
+    pub fn is_find_microsip(&self) -> bool { self.is_find_microsip }
+    pub fn carddav_url(&self) -> &str { self.carddav_url.as_ref() }
+    pub fn carddav_user(&self) -> &str { self.carddav_user.as_ref() }
+    pub fn ldap_url(&self) -> &str { self.ldap_url.as_ref() }
+    pub fn ldap_base(&self) -> &str { self.ldap_base.as_ref() }
+    pub fn ldap_filter(&self) -> &str { self.ldap_filter.as_ref() }
+    pub fn ldap_bind(&self) -> &str { self.ldap_bind.as_ref() }
+    pub fn ldap_mapping(&self) -> &LdapMapping { &self.ldap_mapping }
+    pub fn csv_options(&self) -> &CsvOptions { &self.csv_options }
+    pub fn is_watch(&self) -> bool { self.is_watch }
+    pub fn is_interactive(&self) -> bool { self.is_interactive }
+    pub fn is_watch_xml(&self) -> bool { self.is_watch_xml }
+    pub fn watch_debounce(&self) -> u64 { self.watch_debounce }
+    /// Return input format, "auto" is by extension of input file
+    pub fn input_format(&self) -> &str {
+        match self.input_format.as_str() {
+            "" | INPUT_FORMAT_AUTO => {
+                let ext = Path::new(&self.load_file_name).extension().and_then(|e| e.to_str()).unwrap_or("");
+                match ext.to_lowercase().as_str() {
+                    "csv" => INPUT_FORMAT_CSV,
+                    "xml" => INPUT_FORMAT_XML,
+                    _ => INPUT_FORMAT_VCF,
+                }
+            },
+            format => format,
+        }
+    }
 
-    pub fn print_help(&self) {
-        println!("\n\n{} - Version {} : by {}", _PKG_NAME, _PKG_VERSION, _PKG_AUTHORS);
-        println!("{}", _PKG_DESCRIPTION);
-        println!("\nusage: {} [OPTIONS] \
-            \"path\\to\\load\\*.vcf\" \
-            \"path\\to\\save\\Contacts.xml\" \
-            [\"%PATTERN%\"]",
-            _PKG_NAME
+    /// Write shell completion script of "completions" subcommand
+    pub fn write_completions(&self, out: &mut impl Write) -> Result<(), i32> {
+        let mut buf: Vec<u8> = Vec::new();
+        if let Some(shell) = self.shell {
+            clap_complete::generate(shell, &mut command(), _PKG_NAME, &mut buf);
+        }
+        out.write_all(&buf).map_err(|_| _ERR_WRITE_FILE)
+    }
+
+    /// Write man page of "man" subcommand
+    pub fn write_man(&self, out: &mut impl Write) -> Result<(), i32> {
+        clap_mangen::Man::new(command()).render(out).map_err(|_| _ERR_WRITE_FILE)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Args, ErrorKind> {
+        Args::from_args(args.split(' ')).map_err(|e| e.kind())
+    }
+
+    #[test]
+    fn test_command() {
+        command().debug_assert();
+    }
+
+    #[test]
+    fn test_old_form() {
+        let args = parse("vcf2msip -m -r -n -sd --sort=name in.vcf out/Contacts.xml %name%").unwrap();
+        assert!(args.is_merge() && args.is_renew_logs() && args.is_no_bup() && args.is_speed_dial());
+        assert_eq!(SortOrder::Name, args.sort_order());
+        assert_eq!("in.vcf", args.load_file_name());
+        assert_eq!(native_path("out/Contacts.xml"), args.save_file_name());
+        assert_eq!(native_path("out/MicroSIP.ini"), args.microsip_ini_file());
+        assert_eq!("%name%", args.name_pattern_normal());
+        // Pattern without save file, and options after files
+        let args = parse("vcf2msip -q in.csv %name% -ca").unwrap_err();
+        assert_eq!(ErrorKind::MissingRequiredArgument, args);
+        let args = parse("vcf2msip in.csv out.csv %name% -ca --csv-encoding sjis").unwrap();
+        assert!(args.is_output_csv_agephone());
+        assert_eq!("sjis", args.csv_options().encoding);
+        assert_eq!(LogLevel::Normal, args.log_level());
+        let args = parse("vcf2msip -q --find-microsip").unwrap();
+        assert!(args.is_find_microsip());
+        assert_eq!(LogLevel::Quiet, args.log_level());
+    }
+
+    #[test]
+    fn test_subcommands() {
+        // Old options are replaced only in flag position
+        let args = compat_args("vcf2msip in.vcf out.xml --speed-dial-category -sd -ca -- -sd".split(' '));
+        assert_eq!(
+            ["vcf2msip", "convert", "in.vcf", "out.xml", "--speed-dial-category", "-sd", "--output-csv-agephone", "--", "-sd"].map(OsString::from).to_vec(),
+            args
         );
-        println!("\n---- OPTIONS ----");
-        println!("{:?}\t... Merge from exist MicroSIP contacts too. Default: no merge.", ARG_MERGE);
-        println!("{:?}\t... Do not create backup. Default: create backup.", ARG_OVERWRITE);
-        println!("{:?}\t... Renew name in logs tab. Default: no touch.", ARG_RENEWLOGS);
-        println!("{:?}\t... Just convert to CSV for AGEphone. Default: no.", ARG_OUTPUT_CSV_AGEPHONE);
-        println!("{:?}\t... This message.", ARG_HELP);
-        println!("\n---- PATTERN ----");
-        println!("- Pattern of convert to name from vcf contact.");
-        println!("- Apply to Name column in MicroSIP contacts (and logs if --renew-logs) tab.");
-        println!("- Emptied () and [] are remove at all last.");
-        println!("- Default: \"{}\"", ARG_PAT_DEFAULT);
-        println!("{:?}\t... Full name or Organization name.", ARG_PAT_NAME);
-        println!("{:?}\t... Initial of first name or %name%", ARG_PAT_FIRST_INITIAL);
-        println!("{:?}\t... Initial of last name or %name%", ARG_PAT_LAST_INITIAL);
-        println!("{:?}\t... Telephone type.", ARG_PAT_TEL_TYPE);
-        println!("{:?}\t... Categories string.", ARG_PAT_CATEGORIES);
-        println!("\n");
+        let args = parse("vcf2msip merge in.vcf out.xml --dial-format=%number%p%ext%").unwrap();
+        assert!(args.is_merge() && !args.is_renew_logs());
+        assert_eq!("%number%p%ext%", args.dial_format());
+        assert_eq!(ARG_PAT_DEFAULT, args.name_pattern_normal());
+        let args = parse("vcf2msip renew-logs in.vcf out.xml -v").unwrap();
+        assert!(args.is_renew_logs());
+        assert_eq!(LogLevel::Verbose, args.log_level());
+        let args = parse("vcf2msip export-csv in.vcf out.csv").unwrap();
+        assert!(args.is_output_csv_agephone());
+        assert_eq!("", args.microsip_ini_file());
+        let args = parse("vcf2msip check in.vcf --max-width=10 --json").unwrap();
+        assert!(args.is_check_command() && args.is_json());
+        assert_eq!(10, args.check_max_width());
+        let args = parse("vcf2msip ini --ini-file x.ini set A.b 1 A.c -2 -n").unwrap();
+        assert!(args.is_ini_command() && args.is_no_bup());
+        assert_eq!(vec!["set", "A.b", "1", "A.c", "-2"], args.sub_args());
+        let args = parse("vcf2msip backups -f restore --number 3 out/Contacts.xml").unwrap();
+        assert!(args.is_backups_command() && args.is_force());
+        assert_eq!(vec!["restore", "3"], args.sub_args());
+        assert_eq!(native_path("out/Contacts.xml"), args.save_file_name());
+        let args = parse("vcf2msip backups list out/Contacts.xml").unwrap();
+        assert_eq!(vec!["list"], args.sub_args());
+        assert_eq!(native_path("out/MicroSIP.ini"), args.microsip_ini_file());
+        assert!(parse("vcf2msip completions bash").unwrap().is_completions_command());
+    }
+
+    #[test]
+    fn test_microsip_dir() {
+        // Not found by parsing, given files come first for all commands
+        let dir = MicrosipDir::new("env", Path::new("msip").to_path_buf());
+        for (cmd, xml, ini) in [
+            ("vcf2msip in.vcf", "msip/Contacts.xml", "msip/MicroSIP.ini"),
+            ("vcf2msip in.vcf out/a.xml", "out/a.xml", "out/MicroSIP.ini"),
+            ("vcf2msip in.vcf --ini-file x.ini", "msip/Contacts.xml", "x.ini"),
+            ("vcf2msip backups list", "msip/Contacts.xml", "msip/MicroSIP.ini"),
+            ("vcf2msip ini list", "", "msip/MicroSIP.ini"),
+            ("vcf2msip ini --ini-file x.ini list", "", "x.ini"),
+            ("vcf2msip export-csv in.vcf out.csv", "out.csv", ""),
+        ] {
+            let mut args = parse(cmd).unwrap();
+            assert!(args.save_file_name().is_empty() || !xml.starts_with("msip"), "{}", cmd);
+            assert!(args.microsip_ini_file().is_empty() || !ini.starts_with("msip"), "{}", cmd);
+            args.resolve_microsip_dir(Some(&dir));
+            assert_eq!(native_path(xml), args.save_file_name(), "{}", cmd);
+            assert_eq!(native_path(ini), args.microsip_ini_file(), "{}", cmd);
+        }
+        assert!(!parse("vcf2msip check in.vcf").unwrap().needs_microsip_dir());
+    }
+
+    #[test]
+    fn test_invalid_args() {
+        assert_eq!(Err(ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand), parse("vcf2msip").map(|_| ()));
+        assert_eq!(Err(ErrorKind::DisplayVersion), parse("vcf2msip --version").map(|_| ()));
+        assert_eq!(Err(ErrorKind::ValueValidation), parse("vcf2msip in.vcf out.xml --dial-format=%ext%").map(|_| ()));
+        assert_eq!(Err(ErrorKind::InvalidValue), parse("vcf2msip in.vcf out.xml --sort=age").map(|_| ()));
+        assert_eq!(Err(ErrorKind::InvalidValue), parse("vcf2msip in.txt out.xml").map(|_| ()));
+        assert!(parse("vcf2msip in.txt out.xml --input-format=csv").is_ok());
+        assert_eq!(Err(ErrorKind::ArgumentConflict), parse("vcf2msip in.vcf out.xml --carddav=a --ldap=b").map(|_| ()));
+        assert_eq!(Err(ErrorKind::ArgumentConflict), parse("vcf2msip in.vcf out.xml --watch -i").map(|_| ()));
+        assert_eq!(Err(ErrorKind::ArgumentConflict), parse("vcf2msip in.csv out.xml --carddav=a").map(|_| ()));
+        assert_eq!(Err(ErrorKind::ArgumentConflict), parse("vcf2msip merge in.vcf out.csv -ca").map(|_| ()));
+        assert_eq!(Err(ErrorKind::UnknownArgument), parse("vcf2msip export-csv in.vcf out.csv -m").map(|_| ()));
+        assert_eq!(Err(ErrorKind::UnknownArgument), parse("vcf2msip in.vcf %name% %name%").map(|_| ()));
+        assert_eq!(Err(ErrorKind::WrongNumberOfValues), parse("vcf2msip ini set A.b 1 A.c").map(|_| ()));
+        assert_eq!(Err(ErrorKind::InvalidValue), parse("vcf2msip backups restore 3").map(|_| ()));
+        assert_eq!(Err(ErrorKind::ValueValidation), parse("vcf2msip backups restore -N x").map(|_| ()));
+        assert_eq!(Err(ErrorKind::UnknownArgument), parse("vcf2msip backups restore x 3").map(|_| ()));
+        assert_eq!(Err(ErrorKind::ValueValidation), parse("vcf2msip check in.vcf --max-width 0").map(|_| ()));
     }
 }
@@ -0,0 +1,201 @@
+use crate::error_flg;
+use crate::arg_and_help;
+use crate::file_fns;
+use crate::xml_parser;
+use crate::logger;
+use crate::in_use;
+
+use std::time::{SystemTime, UNIX_EPOCH};
+use error_flg::*;
+use arg_and_help::*;
+use file_fns::*;
+use xml_parser::*;
+use logger::*;
+use in_use::*;
+
+/// Backup files written by one run, they have same number
+#[derive(Debug, PartialEq, Eq)]
+pub struct BackupSet {
+    number: u32,
+    files: Vec<(String, String)>, // original filename, backup filename
+}
+
+impl BackupSet {
+    pub fn number(&self) -> u32 { self.number }
+    pub fn files(&self) -> &[(String, String)] { self.files.as_ref() }
+}
+
+/// Process of "backups" subcommand
+pub fn run_backups_command(args: &Args) -> Result<(), i32> {
+    if args.save_file_name().is_empty() {
+        return Err(_ERR_MICROSIP_NOT_FOUND);
+    }
+    let filenames = [args.save_file_name(), args.microsip_ini_file()];
+    let sub_args = args.sub_args();
+    match sub_args[0].as_str() {
+        "list" => {
+            for set in backup_sets(&filenames) {
+                println!("{}", backup_set_line(&set));
+            }
+        },
+        "restore" => {
+            let number = sub_args.get(1).and_then(|s| s.parse::<u32>().ok());
+            wait_files_free(&filenames, args.wait_secs(), args.is_force())?;
+            let set = restore_backup(&filenames, number, args.is_no_bup())?;
+            for (filename, bup_filename) in set.files() {
+                log_info(&format!("RESTORED {} from {}", filename, bup_filename));
+            }
+        },
+        _ => unreachable!("action is checked at Args"),
+    }
+    Ok(())
+}
+
+/// Return backup sets of files, in ascending order
+pub fn backup_sets(filenames: &[&str]) -> Vec<BackupSet> {
+    let mut sets: Vec<BackupSet> = Vec::new();
+    for filename in filenames {
+        for number in get_bup_numbers(filename) {
+            let bup_filename = make_bup_filename(filename, number);
+            match sets.iter_mut().find(|s| s.number == number) {
+                Some(set) => set.files.push((filename.to_string(), bup_filename)),
+                None => sets.push(BackupSet { number, files: vec![(filename.to_string(), bup_filename)] }),
+            }
+        }
+    }
+    sets.sort_by_key(|s| s.number);
+    sets
+}
+
+/// Return one line of list, like "bup0002  2026-10-19T01:02:03Z  Contacts.xml (6 contacts), MicroSIP.ini"
+fn backup_set_line(set: &BackupSet) -> String {
+    let modified = set.files.iter()
+        .filter_map(|(_, bup)| std::fs::metadata(bup).and_then(|m| m.modified()).ok())
+        .max();
+    let files: Vec<String> = set.files.iter().map(|(filename, bup)| {
+        let name = Path::new(filename).file_name().and_then(|s| s.to_str()).unwrap_or_default();
+        if name.to_lowercase().ends_with(".xml") {
+            let count = SipContacts::new(bup).map_or(0, |sc| sc.data().len());
+            format!("{} ({} contacts)", name, count)
+        } else {
+            name.to_string()
+        }
+    }).collect();
+    format!(
+        "bup{:04}  {}  {}",
+        set.number,
+        modified.map_or("-".to_string(), format_utc),
+        files.join(", ")
+    )
+}
+
+/// Delete backup sets except last `keep` sets, return deleted filenames
+pub fn prune_backups(filenames: &[&str], keep: usize) -> Vec<String> {
+    let sets = backup_sets(filenames);
+    let mut deleted: Vec<String> = Vec::new();
+    for set in &sets[..sets.len().saturating_sub(keep)] {
+        for (_, bup_filename) in &set.files {
+            if remove_file(bup_filename).is_ok() {
+                deleted.push(bup_filename.to_string());
+            }
+        }
+    }
+    deleted
+}
+
+/// Restore all files in backup set `number` or last one, together
+///
+/// Current files are backed up as a new set, unless `is_no_bup`.
+pub fn restore_backup(filenames: &[&str], number: Option<u32>, is_no_bup: bool) -> Result<BackupSet, i32> {
+    let sets = backup_sets(filenames);
+    let set = match number {
+        Some(n) => sets.into_iter().find(|s| s.number == n),
+        None => sets.into_iter().last(),
+    };
+    let Some(set) = set else {
+        return Err(_ERR_BACKUP_NOT_FOUND);
+    };
+    let bup_number = (!is_no_bup).then(|| new_bup_number(filenames));
+    let mut tx = FileTransaction::new(bup_number);
+    for (filename, bup_filename) in &set.files {
+        if copy(bup_filename, tx.add(filename)).is_err() {
+            return Err(_ERR_CREATE_FILE);
+        }
+    }
+    tx.commit()?;
+    tx.verify()?;
+    Ok(set)
+}
+
+/// Return "YYYY-MM-DDThh:mm:ssZ" of UTC
+fn format_utc(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()) as i64;
+    let (days, rem) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // Civil date from days since 1970-01-01
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year, month, day, rem / 3600, rem % 3600 / 60, rem % 60
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::{create_dir_all, read_to_string, remove_dir_all, write};
+    use std::time::Duration;
+
+    #[test]
+    fn test_format_utc() {
+        assert_eq!("1970-01-01T00:00:00Z", format_utc(UNIX_EPOCH));
+        assert_eq!(
+            "2024-02-29T12:34:56Z",
+            format_utc(UNIX_EPOCH + Duration::from_secs(1709210096))
+        );
+    }
+
+    #[test]
+    fn test_backup_sets() {
+        let dir = std::env::temp_dir().join(format!("vcf2msip_test_backups_{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let xml = dir.join("Contacts.xml").to_str().unwrap().to_string();
+        let ini = dir.join("MicroSIP.ini").to_str().unwrap().to_string();
+        let filenames = [xml.as_str(), ini.as_str()];
+        write(&xml, "xml1").unwrap();
+        write(&ini, "ini1").unwrap();
+        // Run 1: both files, Run 2: ini only, Run 3: both files
+        for (run, xml_text, ini_text) in [(1, Some("xml2"), "ini2"), (2, None, "ini3"), (3, Some("xml4"), "ini4")] {
+            let n = new_bup_number(&filenames);
+            assert_eq!(run, n);
+            let mut tx = FileTransaction::new(Some(n));
+            if let Some(text) = xml_text {
+                write(tx.add(&xml), text).unwrap();
+            }
+            write(tx.add(&ini), ini_text).unwrap();
+            tx.commit().unwrap();
+        }
+        let sets = backup_sets(&filenames);
+        assert_eq!(vec![1, 2, 3], sets.iter().map(|s| s.number()).collect::<Vec<u32>>());
+        assert_eq!(1, sets[1].files().len());
+        // Restore run 1 as pair, current files are backed up as set 4
+        restore_backup(&filenames, Some(1), false).unwrap();
+        assert_eq!("xml1", read_to_string(&xml).unwrap());
+        assert_eq!("ini1", read_to_string(&ini).unwrap());
+        assert_eq!(Err(_ERR_BACKUP_NOT_FOUND), restore_backup(&filenames, Some(9), false));
+        // Undo by last set
+        restore_backup(&filenames, None, true).unwrap();
+        assert_eq!("xml4", read_to_string(&xml).unwrap());
+        // Keep last 2 sets
+        assert_eq!(3, prune_backups(&filenames, 2).len());
+        assert_eq!(vec![3, 4], backup_sets(&filenames).iter().map(|s| s.number()).collect::<Vec<u32>>());
+        remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,55 @@
+const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn base64_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| n | ((*b as u32) << (16 - 8 * i)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(TABLE[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Decode base64, white spaces and padding are ignored
+///
+/// None if other character is found. "-" and "_" of URL safe base64 are accepted.
+pub fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    let (mut n, mut bits) = (0u32, 0);
+    for c in text.bytes().filter(|c| !c.is_ascii_whitespace() && *c != b'=') {
+        let v = match c {
+            b'-' => 62,
+            b'_' => 63,
+            _ => TABLE.iter().position(|t| *t == c)? as u32,
+        };
+        n = (n << 6) | v;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((n >> bits) as u8);
+            n &= (1 << bits) - 1;
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_base64() {
+        assert_eq!("dGFybzpzZWNyZXQ=", base64_encode(b"taro:secret"));
+        assert_eq!("YQ==", base64_encode(b"a"));
+        assert_eq!("YWI=", base64_encode(b"ab"));
+        assert_eq!(Some(b"taro:secret".to_vec()), base64_decode("dGFy\r\n bzpz ZWNy\tZXQ="));
+        assert_eq!(Some(b"a".to_vec()), base64_decode("YQ"));
+        assert_eq!(Some(vec![0xff, 0xd8, 0xff]), base64_decode("_9j_"));
+        assert_eq!(None, base64_decode("YQ*="));
+    }
+}
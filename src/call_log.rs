@@ -0,0 +1,232 @@
+#![allow(dead_code)]
+
+use crate::error_flg;
+
+use std::sync::OnceLock;
+use regex::Regex;
+use error_flg::*;
+
+/// One entry of MicroSIP.ini [Calls] section
+///
+/// `<id>=<number>;<name>;<type>;<time>;<duration>;<status>[;<extra>...]`
+/// Older MicroSIP may write less fields, newer one may add extra fields.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallEntry {
+    id: String,
+    number: String,
+    name: String,
+    call_type: String,
+    time: String,
+    duration: String,
+    status: String,
+    extra: Vec<String>,
+    field_count: usize,
+}
+
+impl CallEntry {
+
+    /// Parse one line of [Calls] section
+    pub fn parse(line: &str) -> Option<Self> {
+        let (id, value) = line.trim_end().split_once('=')?;
+        let id = id.trim();
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let fields: Vec<&str> = value.split(';').collect();
+        if fields.len() < 2 || fields[0].trim().is_empty() {
+            return None;
+        }
+        let field = |i: usize| fields.get(i).map_or("", |s| s).to_string();
+        Some(Self {
+            id: id.to_string(),
+            number: field(0),
+            name: field(1),
+            call_type: field(2),
+            time: field(3),
+            duration: field(4),
+            status: field(5),
+            extra: fields.iter().skip(6).map(|s| s.to_string()).collect(),
+            field_count: fields.len(),
+        })
+    }
+
+    /// Return line string, keep original fields count
+    pub fn to_line(&self) -> String {
+        let mut fields: Vec<&str> = vec![
+            &self.number, &self.name, &self.call_type,
+            &self.time, &self.duration, &self.status,
+        ];
+        fields.extend(self.extra.iter().map(|s| s.as_str()));
+        fields.truncate(self.field_count.max(2));
+        format!("{}={}", self.id, fields.join(";"))
+    }
+
+    /// Check number or SIP URI is same
+    pub fn is_match(&self, number: &str) -> bool {
+        is_same_number(number, &self.number)
+    }
+
+    /// Set new name, ';' is field separator so removed
+    pub fn set_name(&mut self, new_name: &str) {
+        self.name = new_name.replace(';', "");
+    }
+
+    pub fn id(&self) -> &str { self.id.as_ref() }
+    pub fn number(&self) -> &str { self.number.as_ref() }
+    pub fn name(&self) -> &str { self.name.as_ref() }
+    pub fn call_type(&self) -> &str { self.call_type.as_ref() }
+    pub fn time(&self) -> &str { self.time.as_ref() }
+    pub fn duration(&self) -> &str { self.duration.as_ref() }
+    pub fn status(&self) -> &str { self.status.as_ref() }
+    pub fn extra(&self) -> &[String] { self.extra.as_ref() }
+}
+
+/// Split main number and extension or DTMF postdial
+///
+/// "03-1234-5678 ext. 205", "+81312345678;ext=205" and "0312345678,,205" have
+/// extension "205". SIP URI is not split.
+pub fn split_ext(number: &str) -> (&str, &str) {
+    static EXT: OnceLock<Regex> = OnceLock::new();
+    let re = EXT.get_or_init(|| Regex::new(
+        r"(?i)^(.*?[0-9)])\s*(?:;\s*ext=|,+|[pw]+|(?:ext\.?|x|内線)\s*)([0-9#*][0-9#*,]*?)[\s,]*$"
+    ).unwrap());
+    let number = number.trim();
+    if is_sip_uri(number) {
+        return (number, "");
+    }
+    match re.captures(number) {
+        Some(c) => (c.get(1).map_or("", |m| m.as_str()), c.get(2).map_or("", |m| m.as_str())),
+        None => (number, ""),
+    }
+}
+
+/// Return key to compare number
+///
+/// "sip:+81-3-1234@pbx.local;transport=udp" and "03-1234" are compared by
+/// numeric only of the user part. Not numeric user part is compared by
+/// lower case, like "sip:Alice@pbx.local" => "alice". Extension is not a part of key.
+pub fn number_key(number: &str) -> String {
+    let number = split_ext(number).0;
+    let lower = number.to_lowercase();
+    let user = ["sip:", "sips:", "tel:"]
+        .iter()
+        .find_map(|scheme| lower.strip_prefix(scheme).map(|_| &number[scheme.len()..]))
+        .unwrap_or(number);
+    let user = user.split(['@', ';']).next().unwrap_or_default();
+    if user.chars().any(|c| c.is_ascii_alphabetic()) {
+        return user.to_lowercase();
+    }
+    fix_number(user)
+}
+
+/// Check number is SIP URI, like "sip:201@pbx.local"
+pub fn is_sip_uri(number: &str) -> bool {
+    let lower = number.trim_start().to_lowercase();
+    lower.starts_with("sip:") || lower.starts_with("sips:")
+}
+
+/// Return lower case domain of SIP URI, empty if no domain
+pub fn number_domain(number: &str) -> String {
+    let Some((_, host)) = number.trim().split_once('@') else {
+        return "".to_string();
+    };
+    host.split([';', '?', ':', '>']).next().unwrap_or_default().to_lowercase()
+}
+
+/// Return key of user part, domain and extension, for same entry check
+pub fn number_domain_key(number: &str) -> String {
+    let key = number_key(number);
+    if key.is_empty() {
+        return key;
+    }
+    let key = match number_domain(number).as_str() {
+        "" => key,
+        domain => format!("{}@{}", key, domain),
+    };
+    match split_ext(number).1 {
+        "" => key,
+        ext => format!("{},{}", key, ext),
+    }
+}
+
+/// Check numbers are same, by user part and domain
+///
+/// Number without domain matches any domain, like "201" and "sip:201@pbx.local".
+pub fn is_same_number(a: &str, b: &str) -> bool {
+    let key = number_key(a);
+    if key.is_empty() || key != number_key(b) {
+        return false;
+    }
+    let (domain_a, domain_b) = (number_domain(a), number_domain(b));
+    domain_a.is_empty() || domain_b.is_empty() || domain_a == domain_b
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_call_entry_parse() {
+        let ce = CallEntry::parse("10=123456789;DDD;2;4000;0;cancel\r").unwrap();
+        assert_eq!("10", ce.id());
+        assert_eq!("123456789", ce.number());
+        assert_eq!("DDD", ce.name());
+        assert_eq!("cancel", ce.status());
+        assert_eq!("10=123456789;DDD;2;4000;0;cancel", ce.to_line());
+        // Newer format with extra fields
+        let mut ce = CallEntry::parse("3=sip:201@pbx.local;Old;1;5000;12;;abc;1").unwrap();
+        assert_eq!(vec!["abc".to_string(), "1".to_string()], ce.extra());
+        ce.set_name("New;Name");
+        assert_eq!("3=sip:201@pbx.local;NewName;1;5000;12;;abc;1", ce.to_line());
+        // Older format with less fields
+        let mut ce = CallEntry::parse("4=+81312345678;Old").unwrap();
+        ce.set_name("New");
+        assert_eq!("4=+81312345678;New", ce.to_line());
+        // Not a call entry
+        assert_eq!(None, CallEntry::parse("shortcutsEnabled=1"));
+        assert_eq!(None, CallEntry::parse("5=broken"));
+    }
+
+    #[test]
+    fn test_number_key() {
+        assert_eq!("1234", number_key("sip:1234@pbx.local"));
+        assert_eq!("81312345678", number_key("+81-3-1234-5678"));
+        assert_eq!("201", number_key("SIP:201@pbx;transport=tcp"));
+        assert_eq!("alice", number_key("sip:Alice@pbx.local"));
+        let ce = CallEntry::parse("0=sip:11-22-33@pbx;AAA;2;1;0;").unwrap();
+        assert!(ce.is_match("112233"));
+        assert!(!ce.is_match("11223"));
+    }
+
+    #[test]
+    fn test_split_ext() {
+        assert_eq!(("03-1234-5678", "205"), split_ext("03-1234-5678 ext. 205"));
+        assert_eq!(("+81312345678", "205"), split_ext("+81312345678;ext=205"));
+        assert_eq!(("0312345678", "205"), split_ext("0312345678,,205"));
+        assert_eq!(("0312345678", "205,,1#"), split_ext("0312345678,205,,1#,"));
+        assert_eq!(("+1 555 1234", "12"), split_ext("+1 555 1234 x12"));
+        assert_eq!(("(03)1234", "5"), split_ext("(03)1234 内線5"));
+        assert_eq!(("0312345678", "9"), split_ext("0312345678p9"));
+        assert_eq!(("090 1234 5678", ""), split_ext("090 1234 5678"));
+        assert_eq!(("1-800-FLOWERS", ""), split_ext("1-800-FLOWERS"));
+        assert_eq!(("sip:201@pbx;x=1", ""), split_ext("sip:201@pbx;x=1"));
+        assert_eq!("0312345678", number_key("0312345678,,205"));
+        assert_eq!("0312345678,205", number_domain_key("03-1234-5678 ext. 205"));
+        let ce = CallEntry::parse("0=03-1234-5678;AAA;2;1;0;").unwrap();
+        assert!(ce.is_match("0312345678,,205"));
+    }
+
+    #[test]
+    fn test_same_number() {
+        assert_eq!("pbx.local", number_domain("sip:201@PBX.local:5060;transport=tcp"));
+        assert_eq!("", number_domain("03-1234"));
+        assert_eq!("201@10.0.0.1", number_domain_key("sip:201@10.0.0.1"));
+        assert_eq!("0312345678", number_domain_key("03-1234-5678"));
+        assert!(is_same_number("sip:201@pbx.local", "SIP:201@PBX.LOCAL;transport=udp"));
+        assert!(is_same_number("201", "sip:201@pbx.local"));
+        assert!(!is_same_number("sip:201@pbx.local", "sip:201@other.example"));
+        assert!(!is_same_number("sip:201@10.0.0.1", "20110001"));
+        assert!(is_same_number("sip:alice@pbx.local", "sip:Alice@pbx.local"));
+        assert!(!is_same_number("sip:@pbx", "sip:@pbx"));
+    }
+}
@@ -0,0 +1,403 @@
+use crate::arg_and_help;
+use crate::base64;
+use crate::error_flg;
+use crate::file_fns;
+use crate::logger;
+use crate::vcf_parser;
+use crate::xml_parser;
+
+use std::env;
+use std::fs::{read_to_string, write};
+use std::time::Duration;
+use regex::Regex;
+use ureq::http::Request;
+use arg_and_help::{ENV_CARDDAV_PASSWORD, ENV_CARDDAV_TOKEN};
+use base64::base64_encode;
+use error_flg::*;
+use file_fns::*;
+use logger::*;
+use vcf_parser::*;
+use xml_parser::{xml_elements, xml_escape, xml_tag_text};
+
+const HREF_PROPERTY: &str = "X-VCF2MSIP-HREF";
+const SYNC_TOKEN_EXT: &str = ".sync-token";
+const MULTIGET_SIZE: usize = 100;
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<D:propfind xmlns:D="DAV:">
+<D:prop><D:resourcetype/><D:getcontenttype/><D:getetag/><D:sync-token/></D:prop>
+</D:propfind>"#;
+
+/// Authentication of CardDAV server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Auth {
+    None,
+    Basic { user: String, password: String },
+    Bearer(String),
+}
+
+impl Auth {
+
+    /// Basic with user and password in environment, or bearer token in environment
+    pub fn from_env(user: &str) -> Self {
+        if !user.is_empty() {
+            let password = env::var(ENV_CARDDAV_PASSWORD).unwrap_or_default();
+            return Self::Basic { user: user.to_string(), password };
+        }
+        match env::var(ENV_CARDDAV_TOKEN) {
+            Ok(token) if !token.is_empty() => Self::Bearer(token),
+            _ => Self::None,
+        }
+    }
+
+    /// Return value of Authorization header
+    fn header(&self) -> Option<String> {
+        match self {
+            Self::None => None,
+            Self::Basic { user, password } => Some(format!("Basic {}", base64_encode(format!("{}:{}", user, password).as_bytes()))),
+            Self::Bearer(token) => Some(format!("Bearer {}", token)),
+        }
+    }
+}
+
+/// Changes from sync-token, by sync-collection report
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SyncChanges {
+    pub changed: Vec<String>, // href
+    pub deleted: Vec<String>, // href
+    pub sync_token: String,
+}
+
+/// Result of pulling to vcf file
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PullResult {
+    pub is_full: bool,
+    pub changed: usize,
+    pub deleted: usize,
+    pub total: usize,
+}
+
+/// CardDAV client of one address book collection
+pub struct CardDav {
+    url: String,
+    auth: Auth,
+    agent: ureq::Agent,
+}
+
+impl CardDav {
+
+    pub fn new(url: &str, auth: Auth) -> Self {
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .http_status_as_error(false)
+            .allow_non_standard_methods(true)
+            .timeout_global(Some(TIMEOUT))
+            .build()
+            .into();
+        Self { url: url.to_string(), auth, agent }
+    }
+
+    /// Send request to address book, return status and body
+    fn request(&self, method: &str, depth: &str, body: String) -> Result<(u16, String), i32> {
+        let mut builder = Request::builder()
+            .method(method)
+            .uri(&self.url)
+            .header("Depth", depth)
+            .header("Content-Type", "application/xml; charset=utf-8");
+        if let Some(auth) = self.auth.header() {
+            builder = builder.header("Authorization", auth);
+        }
+        let Ok(request) = builder.body(body) else {
+            log_warn(&format!("Invalid CardDAV URL: {}", self.url));
+            return Err(_ERR_CARDDAV_REQUEST);
+        };
+        let mut response = match self.agent.run(request) {
+            Ok(r) => r,
+            Err(e) => {
+                log_warn(&format!("{} {}: {}", method, self.url, e));
+                return Err(_ERR_CARDDAV_REQUEST);
+            },
+        };
+        let status = response.status().as_u16();
+        log_record("HTTP", &[("method", method.to_string()), ("url", self.url.clone()), ("status", status.to_string())]);
+        if status == 401 || (status == 403 && method == "PROPFIND") {
+            return Err(_ERR_CARDDAV_AUTH);
+        }
+        let text = response.body_mut().read_to_string().map_err(|_| _ERR_CARDDAV_RESPONSE)?;
+        Ok((status, text))
+    }
+
+    /// Return hrefs of all vcards and sync-token, by PROPFIND
+    pub fn list(&self) -> Result<(Vec<String>, String), i32> {
+        let (status, body) = self.request("PROPFIND", "1", PROPFIND_BODY.to_string())?;
+        if status != 207 {
+            log_warn(&format!("PROPFIND returned status {}", status));
+            return Err(_ERR_CARDDAV_RESPONSE);
+        }
+        let mut hrefs: Vec<String> = Vec::new();
+        let mut sync_token = String::new();
+        for (_, response) in xml_elements(&body, "response") {
+            if let Some(token) = xml_tag_text(response, "sync-token") {
+                sync_token = token;
+            }
+            let is_collection = xml_tag_text(response, "resourcetype").is_some_and(|t| xml_tag_text(&t, "collection").is_some());
+            let is_vcard = xml_tag_text(response, "getcontenttype").is_none_or(|t| t.to_lowercase().contains("vcard"));
+            match xml_tag_text(response, "href") {
+                Some(href) if !is_collection && is_vcard => hrefs.push(href),
+                _ => (),
+            }
+        }
+        Ok((hrefs, sync_token))
+    }
+
+    /// Return (href, vcard) of hrefs, by addressbook-multiget report
+    pub fn multiget(&self, hrefs: &[String]) -> Result<Vec<(String, String)>, i32> {
+        let mut cards: Vec<(String, String)> = Vec::with_capacity(hrefs.len());
+        for chunk in hrefs.chunks(MULTIGET_SIZE) {
+            let mut body = String::from(concat!(
+                r#"<?xml version="1.0" encoding="utf-8"?>"#, "\n",
+                r#"<C:addressbook-multiget xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:carddav">"#, "\n",
+                "<D:prop><D:getetag/><C:address-data/></D:prop>\n",
+            ));
+            for href in chunk {
+                body.push_str(&format!("<D:href>{}</D:href>\n", xml_escape(href)));
+            }
+            body.push_str("</C:addressbook-multiget>");
+            let (status, body) = self.request("REPORT", "1", body)?;
+            if status != 207 {
+                log_warn(&format!("addressbook-multiget returned status {}", status));
+                return Err(_ERR_CARDDAV_RESPONSE);
+            }
+            for (_, response) in xml_elements(&body, "response") {
+                let Some(href) = xml_tag_text(response, "href") else { continue; };
+                match xml_tag_text(response, "address-data") {
+                    Some(vcard) if !vcard.trim().is_empty() => cards.push((href, vcard)),
+                    _ => log_warn(&format!("No address-data of {}, skipped", href)),
+                }
+            }
+        }
+        Ok(cards)
+    }
+
+    /// Return changes from sync-token, None if the server can not sync from it
+    pub fn sync_collection(&self, sync_token: &str) -> Result<Option<SyncChanges>, i32> {
+        let body = format!(concat!(
+            r#"<?xml version="1.0" encoding="utf-8"?>"#, "\n",
+            r#"<D:sync-collection xmlns:D="DAV:">"#, "\n",
+            "<D:sync-token>{}</D:sync-token>\n",
+            "<D:sync-level>1</D:sync-level>\n",
+            "<D:prop><D:getetag/></D:prop>\n",
+            "</D:sync-collection>",
+        ), xml_escape(sync_token));
+        let (status, body) = self.request("REPORT", "0", body)?;
+        if status != 207 {
+            log_verbose(&format!("sync-collection returned status {}, sync all", status));
+            return Ok(None);
+        }
+        let mut changes = SyncChanges::default();
+        for (_, response) in xml_elements(&body, "response") {
+            let Some(href) = xml_tag_text(response, "href") else { continue; };
+            let is_deleted = xml_tag_text(response, "propstat").is_none()
+                && xml_tag_text(response, "status").is_some_and(|s| s.contains(" 404"));
+            if is_deleted {
+                changes.deleted.push(href);
+            } else if !href.ends_with('/') {
+                changes.changed.push(href);
+            }
+        }
+        changes.sync_token = xml_tag_text(&body, "sync-token").unwrap_or_default();
+        Ok(Some(changes))
+    }
+}
+
+/// Fetch vcards to vcf file, incrementally by sync-token of last time
+///
+/// vcf file keeps href of each card in "X-VCF2MSIP-HREF", and sync-token is in "*.vcf.sync-token".
+pub fn pull_to_vcf(client: &CardDav, vcf_filename: &str) -> Result<PullResult, i32> {
+    let token_filename = format!("{}{}", vcf_filename, SYNC_TOKEN_EXT);
+    let last_token = read_to_string(&token_filename).unwrap_or_default().trim().to_string();
+    let mut cards = if last_token.is_empty() { Vec::new() } else { read_cards(vcf_filename) };
+    let mut result = PullResult::default();
+
+    let changes = if last_token.is_empty() { None } else { client.sync_collection(&last_token)? };
+    let sync_token = match changes {
+        Some(changes) => {
+            cards.retain(|(href, _)| !changes.deleted.contains(href) && !changes.changed.contains(href));
+            result.deleted = changes.deleted.len();
+            let fetched = client.multiget(&changes.changed)?;
+            result.changed = fetched.len();
+            cards.extend(fetched);
+            changes.sync_token
+        },
+        None => {
+            let (hrefs, sync_token) = client.list()?;
+            cards = client.multiget(&hrefs)?;
+            result.is_full = true;
+            result.changed = cards.len();
+            sync_token
+        },
+    };
+    result.total = cards.len();
+
+    let mut tx = FileTransaction::new(None);
+    let mut text = String::new();
+    for (href, vcard) in &cards {
+        text.push_str(&card_with_href(vcard, href));
+    }
+    if write(tx.add(vcf_filename), text).is_err() {
+        return Err(_ERR_WRITE_FILE);
+    }
+    if write(tx.add(&token_filename), sync_token).is_err() {
+        return Err(_ERR_WRITE_FILE);
+    }
+    tx.commit()?;
+    Ok(result)
+}
+
+/// Read (href, vcard) from vcf file written by pull_to_vcf
+fn read_cards(vcf_filename: &str) -> Vec<(String, String)> {
+    let Ok(vcf) = Vcf::new(vcf_filename) else { return Vec::new(); };
+    let Ok(reader) = vcf.reader() else { return Vec::new(); };
+    let re = Regex::new(&format!(r"(?m)^{}:(.*?)\r?$", HREF_PROPERTY)).unwrap();
+    reader.flatten()
+        .filter_map(|card| re.captures(&card).map(|c| (c[1].to_string(), format!("{}END:VCARD\r\n", card))))
+        .collect()
+}
+
+/// Return vcard with href property, first vcard only
+fn card_with_href(vcard: &str, href: &str) -> String {
+    let Some(Ok(card)) = VcardReader::new(vcard.as_bytes()).next() else {
+        return String::new();
+    };
+    let mut text = String::with_capacity(card.len() + href.len() + 32);
+    for line in card.lines().filter(|l| !l.starts_with(HREF_PROPERTY)) {
+        text.push_str(line);
+        text.push_str("\r\n");
+    }
+    text.push_str(&format!("{}:{}\r\nEND:VCARD\r\n", HREF_PROPERTY, href));
+    text
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    const CARD_A: &str = "BEGIN:VCARD\r\nVERSION:3.0\r\nFN:Alice\r\nTEL;TYPE=CELL:090-1111-2222\r\nEND:VCARD\r\n";
+    const CARD_B: &str = "BEGIN:VCARD\r\nVERSION:3.0\r\nFN:Bob &amp; Co\r\nTEL;TYPE=WORK:03-1111-2222\r\nEND:VCARD\r\n";
+    const CARD_B2: &str = "BEGIN:VCARD\r\nVERSION:3.0\r\nFN:Bob\r\nTEL;TYPE=WORK:03-9999-2222\r\nEND:VCARD\r\n";
+
+    /// Minimal CardDAV server, return URL and received requests as "METHOD\nheaders\nbody"
+    fn mock_server() -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/addressbooks/taro/contacts/", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let log = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let mut reader = BufReader::new(&stream);
+                let mut head = String::new();
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" { break; }
+                    if let Some(v) = line.to_lowercase().strip_prefix("content-length:") {
+                        length = v.trim().parse().unwrap();
+                    }
+                    head.push_str(&line);
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                let body = String::from_utf8(body).unwrap();
+                let (status, xml) = respond(&head, &body);
+                log.lock().unwrap().push(format!("{}\n{}", head, body));
+                let mut stream = &stream;
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Type: application/xml\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status, xml.len(), xml
+                ).unwrap();
+            }
+        });
+        (url, requests)
+    }
+
+    fn respond(head: &str, body: &str) -> (&'static str, String) {
+        if !head.to_lowercase().contains("authorization: basic dgfybzpzzwnyzxq=") {
+            return ("401 Unauthorized", String::new());
+        }
+        let multistatus = |inner: String| format!(r#"<?xml version="1.0"?><d:multistatus xmlns:d="DAV:" xmlns:card="urn:ietf:params:xml:ns:carddav">{}</d:multistatus>"#, inner);
+        if head.starts_with("PROPFIND") {
+            return ("207 Multi-Status", multistatus(concat!(
+                "<d:response><d:href>/addressbooks/taro/contacts/</d:href><d:propstat><d:prop>",
+                "<d:resourcetype><d:collection/><card:addressbook/></d:resourcetype><d:sync-token>tok1</d:sync-token>",
+                "</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>",
+                "<d:response><d:href>/addressbooks/taro/contacts/a.vcf</d:href><d:propstat><d:prop>",
+                "<d:resourcetype/><d:getcontenttype>text/vcard; charset=utf-8</d:getcontenttype>",
+                "</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>",
+                "<d:response><d:href>/addressbooks/taro/contacts/b%20c.vcf</d:href><d:propstat><d:prop>",
+                "<d:resourcetype/><d:getcontenttype>text/vcard</d:getcontenttype>",
+                "</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>",
+            ).to_string()));
+        }
+        if body.contains("sync-collection") {
+            if !body.contains("<D:sync-token>tok1</D:sync-token>") {
+                return ("403 Forbidden", String::new());
+            }
+            return ("207 Multi-Status", multistatus(concat!(
+                "<d:response><d:href>/addressbooks/taro/contacts/a.vcf</d:href><d:status>HTTP/1.1 404 Not Found</d:status></d:response>",
+                "<d:response><d:href>/addressbooks/taro/contacts/b%20c.vcf</d:href><d:propstat><d:prop><d:getetag>\"2\"</d:getetag>",
+                "</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>",
+                "<d:sync-token>tok2</d:sync-token>",
+            ).to_string()));
+        }
+        let mut inner = String::new();
+        for (href, card) in [("a.vcf", CARD_A), ("b%20c.vcf", if body.contains("a.vcf") { CARD_B } else { CARD_B2 })] {
+            let href = format!("/addressbooks/taro/contacts/{}", href);
+            if body.contains(&format!("<D:href>{}</D:href>", href)) {
+                inner.push_str(&format!(
+                    "<d:response><d:href>{}</d:href><d:propstat><d:prop><card:address-data>{}</card:address-data></d:prop></d:propstat></d:response>",
+                    href, card.replace("\r\n", "&#13;\n")
+                ));
+            }
+        }
+        ("207 Multi-Status", multistatus(inner))
+    }
+
+    #[test]
+    fn test_pull_to_vcf() {
+        let (url, requests) = mock_server();
+        let dir = env::temp_dir().join(format!("vcf2msip_test_carddav_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let vcf = dir.join("contacts.vcf").to_str().unwrap().to_string();
+        let auth = Auth::Basic { user: "taro".to_string(), password: "secret".to_string() };
+        // Full sync by PROPFIND and multiget
+        let client = CardDav::new(&url, auth);
+        let result = pull_to_vcf(&client, &vcf).unwrap();
+        assert_eq!(PullResult { is_full: true, changed: 2, deleted: 0, total: 2 }, result);
+        assert_eq!("tok1", read_to_string(format!("{}.sync-token", vcf)).unwrap());
+        let names: Vec<String> = Vcf::new(&vcf).unwrap().reader().unwrap()
+            .map(|c| Contact::new(&c.unwrap()).full_name().to_string()).collect();
+        assert_eq!(vec!["Alice", "Bob & Co"], names);
+        assert!(read_to_string(&vcf).unwrap().contains("X-VCF2MSIP-HREF:/addressbooks/taro/contacts/b%20c.vcf\r\nEND:VCARD"));
+        // Incremental sync, a.vcf is deleted and b c.vcf is changed
+        let result = pull_to_vcf(&client, &vcf).unwrap();
+        assert_eq!(PullResult { is_full: false, changed: 1, deleted: 1, total: 1 }, result);
+        assert_eq!("tok2", read_to_string(format!("{}.sync-token", vcf)).unwrap());
+        let cards = read_cards(&vcf);
+        assert_eq!(1, cards.len());
+        assert!(cards[0].1.contains("TEL;TYPE=WORK:03-9999-2222"));
+        // Unknown sync-token falls back to full sync
+        let result = pull_to_vcf(&client, &vcf).unwrap();
+        assert!(result.is_full);
+        assert_eq!(["PROPFIND", "REPORT", "REPORT", "REPORT", "REPORT", "PROPFIND", "REPORT"].to_vec(),
+            requests.lock().unwrap().iter().map(|r| r.split(' ').next().unwrap()).collect::<Vec<&str>>());
+        // Wrong password
+        let client = CardDav::new(&url, Auth::Bearer("wrong".to_string()));
+        assert_eq!(Err(_ERR_CARDDAV_AUTH), pull_to_vcf(&client, dir.join("other.vcf").to_str().unwrap()));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
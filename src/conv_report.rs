@@ -0,0 +1,176 @@
+use crate::error_flg;
+use crate::json_writer;
+use crate::logger;
+
+use error_flg::*;
+use json_writer::*;
+use logger::*;
+
+pub const SKIP_NO_NUMBER: &str = "no telephone number";
+
+/// Counters of converting process
+#[derive(Debug, Default)]
+pub struct ProcCounter {
+    pub all_contact: usize,
+    pub all_telephone: usize,
+    pub contact: usize,
+    pub telephone: usize,
+    pub merge: usize,
+    pub logs: usize,
+    pub speed_dial: usize,
+}
+
+impl ProcCounter {
+    pub fn add_count(&mut self, pc: &ProcCounter) {
+        self.all_contact += pc.all_contact;
+        self.all_telephone += pc.all_telephone;
+        self.contact += pc.contact;
+        self.telephone += pc.telephone;
+        self.merge += pc.merge;
+        self.logs += pc.logs;
+        self.speed_dial += pc.speed_dial;
+    }
+    pub fn print(&self) {
+        log_info(&format!(
+            "ALL VCF CONTACTS: {} / ALL VCF TELEPHONES: {}",
+            self.all_contact, self.all_telephone
+        ));
+        log_info(&format!(
+            "PROCESSED [Contact:{}, Telephone:{}, Merge:{}, RenewLogs:{}, SpeedDial:{}]",
+            self.contact,
+            self.telephone,
+            self.merge,
+            self.logs,
+            self.speed_dial
+        ));
+    }
+    pub fn to_json(&self) -> JsonValue {
+        JsonValue::object(vec![
+            ("all_contact", JsonValue::num(self.all_contact)),
+            ("all_telephone", JsonValue::num(self.all_telephone)),
+            ("contact", JsonValue::num(self.contact)),
+            ("telephone", JsonValue::num(self.telephone)),
+            ("merge", JsonValue::num(self.merge)),
+            ("logs", JsonValue::num(self.logs)),
+            ("speed_dial", JsonValue::num(self.speed_dial)),
+        ])
+    }
+}
+
+/// Result of one vcf contact
+#[derive(Debug, PartialEq, Eq)]
+pub struct ContactResult {
+    pub card: usize,
+    pub line: usize,
+    pub name: String,
+    pub skip_reason: Option<&'static str>,
+    pub numbers: Vec<(String, String)>, // number, converted name
+    pub photo: Option<String>, // written image file or URI
+}
+
+/// Same number as other entry
+///
+/// `source` is "xml" if the original contact is replaced, "vcf" if both are written.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Collision {
+    pub number: String,
+    pub name: String,
+    pub other_name: String,
+    pub source: &'static str,
+}
+
+/// Renamed entry of call logs
+#[derive(Debug, PartialEq, Eq)]
+pub struct RenamedLog {
+    pub id: String,
+    pub number: String,
+    pub old_name: String,
+    pub new_name: String,
+}
+
+/// Details of converting process, for "--report json"
+#[derive(Debug, Default)]
+pub struct ConvReport {
+    pub pc: ProcCounter,
+    pub contacts: Vec<ContactResult>,
+    pub merged: Vec<(String, String)>, // number, name
+    pub collisions: Vec<Collision>,
+    pub renamed_logs: Vec<RenamedLog>,
+    pub backups: Vec<String>,
+    pub pruned_backups: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl ConvReport {
+
+    /// Return json, with error if process is failed
+    pub fn to_json(&self, error: Option<i32>) -> JsonValue {
+        let number_json = |(number, name): &(String, String)| JsonValue::object(vec![
+            ("number", JsonValue::str(number)),
+            ("name", JsonValue::str(name)),
+        ]);
+        let contacts = self.contacts.iter().map(|c| JsonValue::object(vec![
+            ("card", JsonValue::num(c.card)),
+            ("line", JsonValue::num(c.line)),
+            ("name", JsonValue::str(&c.name)),
+            ("status", JsonValue::str(if c.skip_reason.is_some() { "skipped" } else { "converted" })),
+            ("reason", c.skip_reason.map_or(JsonValue::Null, JsonValue::str)),
+            ("numbers", JsonValue::Array(c.numbers.iter().map(number_json).collect())),
+            ("photo", c.photo.as_ref().map_or(JsonValue::Null, |p| JsonValue::str(p))),
+        ])).collect();
+        let collisions = self.collisions.iter().map(|c| JsonValue::object(vec![
+            ("number", JsonValue::str(&c.number)),
+            ("name", JsonValue::str(&c.name)),
+            ("other_name", JsonValue::str(&c.other_name)),
+            ("source", JsonValue::str(c.source)),
+        ])).collect();
+        let renamed_logs = self.renamed_logs.iter().map(|r| JsonValue::object(vec![
+            ("id", JsonValue::str(&r.id)),
+            ("number", JsonValue::str(&r.number)),
+            ("old_name", JsonValue::str(&r.old_name)),
+            ("new_name", JsonValue::str(&r.new_name)),
+        ])).collect();
+        let strings = |v: &[String]| JsonValue::Array(v.iter().map(|s| JsonValue::str(s)).collect());
+        JsonValue::object(vec![
+            ("status", JsonValue::str(if error.is_some() { "error" } else { "ok" })),
+            ("error", error.map_or(JsonValue::Null, |e| JsonValue::object(vec![
+                ("code", JsonValue::Number(e as i64)),
+                ("message", JsonValue::str(err_msg(e))),
+            ]))),
+            ("counters", self.pc.to_json()),
+            ("contacts", JsonValue::Array(contacts)),
+            ("merged", JsonValue::Array(self.merged.iter().map(number_json).collect())),
+            ("collisions", JsonValue::Array(collisions)),
+            ("renamed_logs", JsonValue::Array(renamed_logs)),
+            ("backups", strings(&self.backups)),
+            ("pruned_backups", strings(&self.pruned_backups)),
+            ("warnings", strings(&self.warnings)),
+        ])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_report_json() {
+        let mut report = ConvReport::default();
+        report.pc.contact = 1;
+        report.contacts.push(ContactResult {
+            card: 1, line: 1, name: "A".to_string(), skip_reason: None,
+            numbers: vec![("11".to_string(), "A (WORK)".to_string())], photo: Some("photos/11.jpg".to_string()),
+        });
+        report.contacts.push(ContactResult {
+            card: 2, line: 5, name: "B".to_string(), skip_reason: Some(SKIP_NO_NUMBER), numbers: vec![], photo: None,
+        });
+        report.backups.push("Contacts.xml.bup0001".to_string());
+        let json = report.to_json(None).to_string();
+        assert!(json.starts_with(r#"{"status":"ok","error":null,"counters":{"all_contact":0,"all_telephone":0,"contact":1,"#));
+        assert!(json.contains(r#"{"card":1,"line":1,"name":"A","status":"converted","reason":null,"numbers":[{"number":"11","name":"A (WORK)"}],"photo":"photos/11.jpg"}"#));
+        assert!(json.contains(r#"{"card":2,"line":5,"name":"B","status":"skipped","reason":"no telephone number","numbers":[],"photo":null}"#));
+        assert!(json.contains(r#""backups":["Contacts.xml.bup0001"]"#));
+        let json = report.to_json(Some(_ERR_FILE_NOT_FOUND)).to_string();
+        assert!(json.starts_with(r#"{"status":"error","error":{"code":1,"message":"File not found."}"#));
+    }
+}
@@ -0,0 +1,332 @@
+use crate::call_log;
+use crate::error_flg;
+use crate::logger;
+use crate::vcf_parser;
+
+use std::fs::read;
+use encoding_rs::{SHIFT_JIS, UTF_8};
+use kanaria::{string::UCSStr, utils::ConvertTarget};
+use call_log::{is_sip_uri, split_ext};
+use error_flg::*;
+use logger::*;
+use vcf_parser::*;
+
+pub const CSV_ENCODINGS: &[&str] = &["auto", "utf-8", "sjis"];
+pub const CSV_HEADERS: &[&str] = &["auto", "yes", "no"];
+const CSV_MAP_NO_HEADER: &str = "name=1,tel.WORK=2";
+
+/// Aliases of contact fields for CSV
+const FIELD_ALIASES: &[(&str, &str)] = &[
+    ("reading", "phonetic-last"),
+    ("company", "org"),
+    ("category", "categories"),
+];
+
+/// Header names of contact fields, lower case
+const HEADER_NAMES: &[(&str, &[&str])] = &[
+    ("name", &["name", "full name", "fullname", "display name", "氏名", "名前", "お名前", "表示名"]),
+    ("last", &["last name", "lastname", "surname", "family name", "姓"]),
+    ("first", &["first name", "firstname", "given name", "名"]),
+    ("phonetic-last", &["reading", "furigana", "yomi", "phonetic name", "フリガナ", "ふりがな", "よみがな", "読み"]),
+    ("org", &["company", "organization", "organisation", "会社", "会社名", "所属", "部署"]),
+    ("categories", &["category", "categories", "group", "グループ", "分類"]),
+];
+
+/// Header words of telephone columns and its type, lower case, first match is used
+///
+/// ASCII words match a whole word of header, like "tel" matches "TEL1" but not "Hotel".
+const TEL_HEADER_WORDS: &[(&str, &str)] = &[
+    ("fax", "FAX"), ("ファックス", "FAX"),
+    ("mobile", "CELL"), ("cell", "CELL"), ("携帯", "CELL"),
+    ("home", "HOME"), ("自宅", "HOME"),
+    ("phone", "WORK"), ("telephone", "WORK"), ("tel", "WORK"), ("電話", "WORK"), ("内線", "WORK"),
+];
+
+/// Header words of columns describing a telephone column, like Google "Phone 1 - Type"
+const TEL_META_WORDS: &[&str] = &["type", "label"];
+
+/// Options of CSV input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvOptions {
+    pub map: String,
+    pub encoding: String,
+    pub header: String,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        Self { map: String::new(), encoding: "auto".to_string(), header: "auto".to_string() }
+    }
+}
+
+/// Column of mapping, by 1 based number or header name
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Column {
+    Index(usize),
+    Name(String),
+}
+
+/// Parse "field=column,...", None if unknown field or column 0
+fn parse_map(text: &str) -> Option<Vec<(String, Column)>> {
+    let mut map: Vec<(String, Column)> = Vec::new();
+    for item in text.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        let (field, column) = item.split_once('=')?;
+        let field = field.trim();
+        let field = FIELD_ALIASES.iter().find(|(a, _)| *a == field).map_or(field, |(_, f)| f);
+        if !ContactParts::is_field(field) {
+            return None;
+        }
+        let column = match column.trim().parse::<usize>() {
+            Ok(0) => return None,
+            Ok(n) => Column::Index(n),
+            Err(_) => Column::Name(column.trim().to_string()),
+        };
+        map.push((field.to_string(), column));
+    }
+    Some(map)
+}
+
+/// Check "--csv-map" value
+pub fn is_valid_map(text: &str) -> bool {
+    parse_map(text).is_some()
+}
+
+/// Return field of header name, by known names
+fn header_field(header: &str) -> Option<String> {
+    let header = header.trim().to_lowercase();
+    if let Some((field, _)) = HEADER_NAMES.iter().find(|(_, names)| names.contains(&header.as_str())) {
+        return Some(field.to_string());
+    }
+    if label_words(&header).any(|w| TEL_META_WORDS.contains(&w)) {
+        return None;
+    }
+    teltype_of_label(&header).map(|teltype| format!("{}{}", TEL_FIELD_PREFIX, teltype))
+}
+
+/// Split label to words, trailing numbers are removed, like "TEL1" is "tel"
+fn label_words(label: &str) -> impl Iterator<Item = &str> {
+    label.split(|c: char| !c.is_alphanumeric())
+        .map(|w| w.trim_end_matches(|c: char| c.is_ascii_digit()))
+        .filter(|w| !w.is_empty())
+}
+
+/// Return telephone type of label, like "Mobile Phone" is "CELL"
+pub fn teltype_of_label(label: &str) -> Option<&'static str> {
+    let label = label.to_lowercase();
+    TEL_HEADER_WORDS.iter().find(|(word, _)| if word.is_ascii() {
+        label_words(&label).any(|w| w == *word)
+    } else {
+        label.contains(word)
+    }).map(|(_, teltype)| *teltype)
+}
+
+/// Check cell looks like a telephone number
+fn is_number_like(cell: &str) -> bool {
+    let digits = cell.chars().filter(|c| c.is_ascii_digit()).count();
+    digits >= 3 && cell.chars().all(|c| c.is_ascii_digit() || "+-() .".contains(c))
+}
+
+/// Check cell of telephone column is a number, SIP URI or number with extension
+fn is_tel_cell(cell: &str) -> bool {
+    let cell = UCSStr::from_str(cell.trim()).narrow(ConvertTarget::NUMBER | ConvertTarget::SYMBOL).to_string();
+    is_sip_uri(&cell) || is_number_like(split_ext(&cell).0)
+}
+
+/// Decode bytes by encoding, "auto" is UTF-8 (or BOM) if valid, else Shift_JIS
+fn decode(bytes: &[u8], encoding: &str) -> String {
+    let is_utf8 = match encoding {
+        "utf-8" => true,
+        "sjis" => false,
+        _ => std::str::from_utf8(bytes).is_ok() || bytes.starts_with(&[0xff, 0xfe]) || bytes.starts_with(&[0xfe, 0xff]),
+    };
+    let (text, _, _) = if is_utf8 { UTF_8.decode(bytes) } else { SHIFT_JIS.decode(bytes) };
+    text.into_owned()
+}
+
+/// Return delimiter of first line, comma, tab or semicolon
+fn detect_delimiter(text: &str) -> char {
+    let line = text.lines().next().unwrap_or_default();
+    let mut counts = [(',', 0), ('\t', 0), (';', 0)];
+    let mut is_quoted = false;
+    for c in line.chars() {
+        if c == '"' { is_quoted = !is_quoted; }
+        if is_quoted { continue; }
+        if let Some(count) = counts.iter_mut().find(|(d, _)| *d == c) {
+            count.1 += 1;
+        }
+    }
+    counts.iter().fold((',', 0), |max, &c| if c.1 > max.1 { c } else { max }).0
+}
+
+/// Parse CSV text, quoted cell may have delimiter, "" and line break
+pub fn parse_csv(text: &str, delimiter: char) -> Vec<Vec<String>> {
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    let mut row: Vec<String> = Vec::new();
+    let mut cell = String::new();
+    let mut is_quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if is_quoted && chars.peek() == Some(&'"') => { cell.push('"'); chars.next(); },
+            '"' if is_quoted => is_quoted = false,
+            '"' if cell.is_empty() => is_quoted = true,
+            _ if is_quoted => cell.push(c),
+            '\r' if chars.peek() == Some(&'\n') => (),
+            '\r' | '\n' => {
+                row.push(std::mem::take(&mut cell));
+                rows.push(std::mem::take(&mut row));
+            },
+            _ if c == delimiter => row.push(std::mem::take(&mut cell)),
+            _ => cell.push(c),
+        }
+    }
+    if !cell.is_empty() || !row.is_empty() {
+        row.push(cell);
+        rows.push(row);
+    }
+    rows.retain(|r| r.iter().any(|c| !c.trim().is_empty()));
+    rows
+}
+
+/// Read CSV file in encoding, return rows
+pub fn read_csv(filename: &str, encoding: &str) -> Result<Vec<Vec<String>>, i32> {
+    let Ok(bytes) = read(filename) else {
+        return Err(_ERR_READ_FILE);
+    };
+    Ok(csv_rows(&decode(&bytes, encoding)))
+}
+
+/// Return rows of CSV text, delimiter is detected
+fn csv_rows(text: &str) -> Vec<Vec<String>> {
+    let text = text.trim_start_matches('\u{feff}');
+    parse_csv(text, detect_delimiter(text))
+}
+
+/// Return contacts of CSV rows, by column mapping or header
+pub fn csv_contacts(rows: &[Vec<String>], options: &CsvOptions) -> Result<Vec<ContactParts>, i32> {
+    let Some(first_row) = rows.first() else {
+        return Ok(Vec::new());
+    };
+    let map = parse_map(&options.map).unwrap_or_default();
+    let is_header = match options.header.as_str() {
+        "yes" => true,
+        "no" => false,
+        _ => map.iter().any(|(_, c)| matches!(c, Column::Name(_))) || !first_row.iter().any(|c| is_number_like(c)),
+    };
+    // Field and index of column
+    let columns: Vec<(String, usize)> = if !map.is_empty() {
+        let mut columns = Vec::with_capacity(map.len());
+        for (field, column) in map {
+            let index = match column {
+                Column::Index(n) => n - 1,
+                Column::Name(name) if is_header => match first_row.iter().position(|h| h.trim().eq_ignore_ascii_case(&name)) {
+                    Some(i) => i,
+                    None => {
+                        log_warn(&format!("CSV column \"{}\" not found in header", name));
+                        return Err(_ERR_CSV_COLUMN);
+                    },
+                },
+                Column::Name(_) => return Err(_ERR_CSV_COLUMN),
+            };
+            columns.push((field, index));
+        }
+        columns
+    } else if is_header {
+        first_row.iter().enumerate().filter_map(|(i, h)| header_field(h).map(|f| (f, i))).collect()
+    } else {
+        parse_map(CSV_MAP_NO_HEADER).unwrap_or_default().into_iter()
+            .filter_map(|(f, c)| match c { Column::Index(n) => Some((f, n - 1)), _ => None })
+            .collect()
+    };
+    if !columns.iter().any(|(f, _)| f.starts_with(TEL_FIELD_PREFIX)) {
+        log_warn("No telephone column in CSV, give --csv-map");
+        return Err(_ERR_CSV_COLUMN);
+    }
+    for (field, index) in &columns {
+        log_record("COLUMN", &[("field", field.clone()), ("column", (index + 1).to_string())]);
+    }
+    let body = if is_header { &rows[1..] } else { rows };
+    Ok(body.iter().map(|row| {
+        let mut parts = ContactParts::default();
+        for (field, index) in &columns {
+            let Some(value) = row.get(*index) else { continue };
+            if field.starts_with(TEL_FIELD_PREFIX) && !value.trim().is_empty() && !is_tel_cell(value) {
+                log_record("SKIP", &[("field", field.clone()), ("value", value.clone()), ("reason", "not a number".to_string())]);
+                continue;
+            }
+            parts.set(field, value);
+        }
+        parts
+    }).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn options(map: &str, header: &str) -> CsvOptions {
+        CsvOptions { map: map.to_string(), header: header.to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let text = "a,\"b,1\",\"c \"\"q\"\"\"\r\n\r\n\"multi\r\nline\",,x\ny";
+        assert_eq!(
+            vec![vec!["a", "b,1", "c \"q\""], vec!["multi\r\nline", "", "x"], vec!["y"]],
+            parse_csv(text, ',')
+        );
+        assert_eq!('\t', detect_delimiter("name\ttel\t\"a,b,c\"\n"));
+        assert_eq!(';', detect_delimiter("name;tel\n"));
+        assert_eq!(',', detect_delimiter("name\n"));
+        let (sjis, _, _) = SHIFT_JIS.encode("山田,03-1111-2222");
+        assert_eq!("山田,03-1111-2222", decode(&sjis, "auto"));
+        assert_eq!("山田", decode("\u{feff}山田".as_bytes(), "auto"));
+    }
+
+    #[test]
+    fn test_csv_contacts() {
+        // Header names are found
+        let text = "氏名,フリガナ,会社名,グループ,電話番号,携帯電話,FAX\r\n\
+            山田 太郎,やまだ たろう,ACME,Sales,03-1111-2222,090-1111-2222,03-1111-3333\r\n";
+        let contacts = csv_contacts(&csv_rows(text), &CsvOptions::default()).unwrap();
+        assert_eq!(1, contacts.len());
+        let ct = Contact::new(&contacts[0].to_vcard());
+        assert_eq!("山田 太郎", ct.full_name());
+        assert_eq!("や", ct.linitial());
+        assert_eq!("Sales", ct.first_categories());
+        let tels: Vec<(&str, &str)> = ct.tel_iter().map(|t| (t.teltype(), t.number())).collect();
+        assert_eq!(vec![("WORK", "03-1111-2222"), ("CELL", "090-1111-2222"), ("FAX", "03-1111-3333")], tels);
+        // Map by header name and number
+        let text = "Customer\tKana\tMain\tSub\nACME Inc.\tあくめ\t03-1111-2222\t\n";
+        let contacts = csv_contacts(&csv_rows(text), &options("company=Customer,reading=2,tel.WORK=Main,tel.HOME=4", "auto")).unwrap();
+        assert_eq!("ACME Inc.", contacts[0].org);
+        assert_eq!("あくめ", contacts[0].phonetic_last);
+        assert_eq!(vec![("WORK".to_string(), "03-1111-2222".to_string())], contacts[0].tels);
+        // No header is detected by number
+        let contacts = csv_contacts(&csv_rows("Reception,100\nShop,200\n"), &CsvOptions::default()).unwrap();
+        assert_eq!(2, contacts.len());
+        assert_eq!("Reception", contacts[0].name);
+        let contacts = csv_contacts(&csv_rows("Reception,100\n"), &options("", "yes")).unwrap_or_default();
+        assert!(contacts.is_empty());
+        // Errors
+        assert_eq!(Err(_ERR_CSV_COLUMN), csv_contacts(&csv_rows("name,tel\nA,1\n"), &options("tel.WORK=phone", "auto")));
+        assert_eq!(Err(_ERR_CSV_COLUMN), csv_contacts(&csv_rows("name,note\nA,1\n"), &CsvOptions::default()));
+        assert!(!is_valid_map("mail=3"));
+        assert!(!is_valid_map("name=0"));
+        assert!(is_valid_map("category=Group, tel.CELL=5"));
+        // Whole words of header, not a number is skipped
+        assert_eq!(None, header_field("Phonetic Last Name"));
+        assert_eq!(None, header_field("Hotel"));
+        assert_eq!(None, header_field("Telegram"));
+        assert_eq!(None, header_field("Phone 1 - Type"));
+        assert_eq!(Some("tel.WORK".to_string()), header_field("Phone 1 - Value"));
+        assert_eq!(Some("tel.WORK".to_string()), header_field("Business Phone"));
+        assert_eq!(Some("tel.WORK".to_string()), header_field("TEL1"));
+        assert_eq!(Some("tel.FAX".to_string()), header_field("Business Fax"));
+        let text = "Name,Phone 1 - Type,Phone 1 - Value,Phone 2 - Value\n\
+            A,Mobile,０９０－１１１１－２２２２,see note\nB,Work,03-1111-2222 ext. 205,sip:201@pbx.local\n";
+        let contacts = csv_contacts(&csv_rows(text), &CsvOptions::default()).unwrap();
+        assert_eq!(vec![("WORK".to_string(), "０９０－１１１１－２２２２".to_string())], contacts[0].tels);
+        assert_eq!(2, contacts[1].tels.len());
+    }
+}
@@ -1,27 +1,68 @@
+use crate::logger::log_error;
+
 pub const _ERR_FILE_NOT_FOUND: i32 = 1;
 pub const _ERR_CREATE_FILE: i32 = 2;
 pub const _ERR_WRITE_FILE: i32 = 3;
 pub const _ERR_READ_FILE: i32 = 4;
+pub const _ERR_MICROSIP_NOT_FOUND: i32 = 5;
 
 pub const _ERR_DID_NOT_RUN_RENEW_LOGS: i32 = 101;
 pub const _ERR_WRITE_INI_FILE: i32 = 102;
+pub const _ERR_INI_KEY_NOT_FOUND: i32 = 103;
+pub const _ERR_INI_INVALID_KEY: i32 = 104;
 
 pub const _ERR_FIX_FILE_COPY: i32 = 201;
 pub const _ERR_FILE_BACKUP: i32 = 202;
+pub const _ERR_BACKUP_NOT_FOUND: i32 = 203;
+pub const _ERR_MICROSIP_RUNNING: i32 = 204;
+pub const _ERR_FILE_CHANGED: i32 = 205;
+pub const _ERR_VERIFY_FAILED: i32 = 206;
+
+pub const _ERR_CHECK_FOUND_ERRORS: i32 = 301;
+
+pub const _ERR_FEATURE_DISABLED: i32 = 401;
+pub const _ERR_CARDDAV_REQUEST: i32 = 402;
+pub const _ERR_CARDDAV_AUTH: i32 = 403;
+pub const _ERR_CARDDAV_RESPONSE: i32 = 404;
+pub const _ERR_LDAP_CONNECT: i32 = 405;
+pub const _ERR_LDAP_BIND: i32 = 406;
+pub const _ERR_LDAP_SEARCH: i32 = 407;
+pub const _ERR_CSV_COLUMN: i32 = 408;
 
 pub fn print_err_msg(e: i32) {
-    let msg = match e {
+    let msg = err_msg(e);
+    if !msg.is_empty() { log_error(msg) } else { log_error(&format!("Unknown error {}", e)) };
+}
+
+/// Return message of error code, empty if unknown
+pub fn err_msg(e: i32) -> &'static str {
+    match e {
         _ERR_FILE_NOT_FOUND => "File not found.",
         _ERR_CREATE_FILE => "File create failed.",
         _ERR_READ_FILE => "File read failed.",
         _ERR_WRITE_FILE => "File write failed.",
+        _ERR_MICROSIP_NOT_FOUND => "MicroSIP data directory not found. Give path of Contacts.xml, or set VCF2MSIP_MICROSIP_DIR.",
         _ERR_DID_NOT_RUN_RENEW_LOGS => "MicroSIP.ini is not renewed.",
         _ERR_WRITE_INI_FILE => "MicroSIP.ini write failed.",
+        _ERR_INI_KEY_NOT_FOUND => "Key not found in MicroSIP.ini.",
+        _ERR_INI_INVALID_KEY => "Key must be SECTION.KEY format.",
         _ERR_FIX_FILE_COPY => "Failed to replace the created file.",
         _ERR_FILE_BACKUP => "File backup failed.",
+        _ERR_BACKUP_NOT_FOUND => "Backup not found.",
+        _ERR_MICROSIP_RUNNING => "Close MicroSIP, or use --wait or --force.",
+        _ERR_FILE_CHANGED => "File was changed while running, nothing is written. Try again.",
+        _ERR_VERIFY_FAILED => "Written file was changed after writing, maybe by MicroSIP.",
+        _ERR_CHECK_FOUND_ERRORS => "Check found errors in vcf.",
+        _ERR_FEATURE_DISABLED => "This build does not have the feature.",
+        _ERR_CARDDAV_REQUEST => "Could not connect to CardDAV server.",
+        _ERR_CARDDAV_AUTH => "CardDAV server denied the login.",
+        _ERR_CARDDAV_RESPONSE => "Unexpected response from CardDAV server.",
+        _ERR_LDAP_CONNECT => "Could not connect to LDAP server.",
+        _ERR_LDAP_BIND => "LDAP server denied the login.",
+        _ERR_LDAP_SEARCH => "LDAP search failed.",
+        _ERR_CSV_COLUMN => "CSV column is not found, check --csv-map.",
         _ => "",
-    };
-    if !msg.is_empty() { println!("ERROR: {}", msg) } else { println!("Unknown error {}", e) };
+    }
 }
 
 /// Return numeric only
@@ -1,5 +1,4 @@
 use crate::error_flg;
-use crate::vcf_parser;
 use crate::arg_and_help;
 
 pub use std::path::MAIN_SEPARATOR;
@@ -7,29 +6,237 @@ pub use std::{env, path::Path};
 pub use std::fs::{copy, read_dir, remove_file, rename};
 // pub use std::io::prelude::*;
 
+use crate::logger;
+
 use error_flg::*;
-use vcf_parser::*;
 use arg_and_help::*;
+use logger::{log_error, log_warn};
+use std::fs::metadata;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::time::SystemTime;
 
 /// Return filename + ".tmp"
 pub fn make_tmp_filename(filename: &str) -> String {
     format!("{}.tmp", &filename)
 }
 
+/// Temporary file in temp directory, deleted at drop
+pub struct TempFile {
+    filename: String,
+}
+
+impl TempFile {
+
+    pub fn new(name: &str) -> Self {
+        let path = env::temp_dir().join(format!("vcf2msip_{}_{}", std::process::id(), name));
+        Self { filename: path.to_string_lossy().to_string() }
+    }
+
+    pub fn filename(&self) -> &str { self.filename.as_ref() }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        delete_file(&self.filename);
+    }
+}
+
 /// Delete file
 pub fn delete_file(filename: &str) {
-    if let Err(_) = remove_file(&filename) {()};
+    if remove_file(filename).is_err() {};
 }
 
-/// File backup with auto increment filename
-pub fn file_backup(filename: &str) -> Result<(), i32> {
-    let bup_filename = get_new_bup_filename(&filename);
-    match copy(&filename, &bup_filename) {
-        Ok(_) => Ok(()),
+/// File backup with numbered filename, return backup filename
+pub fn file_backup(filename: &str, bup_number: u32) -> Result<String, i32> {
+    let bup_filename = make_bup_filename(filename, bup_number);
+    match copy(filename, &bup_filename) {
+        Ok(_) => Ok(bup_filename),
         Err(_) => Err(_ERR_FILE_BACKUP),
     }
 }
 
+/// Step of committing transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TxStep {
+    Prepare,
+    Check,
+    Backup,
+    Move,
+    Replace,
+}
+
+/// One file of transaction
+#[derive(Debug)]
+struct TxFile {
+    filename: String,
+    tmp_filename: String,
+    old_filename: String,
+    stamp: Option<(u64, SystemTime)>, // size and modified time of original, when added
+    written_hash: u64,
+    is_moved: bool,
+    is_replaced: bool,
+}
+
+/// Replace several files together, or none of them
+///
+/// 1. Caller adds files before reading originals, then writes new contents
+///    to temporary files of `add`.
+/// 2. `commit` checks originals are not changed by other program since added,
+///    backs up all originals, moves them to "*.old",
+///    renames all temporary files into place, then deletes "*.old".
+/// 3. On any failure, previous state is restored and backups of this run are deleted.
+/// 4. `verify` re-reads replaced files, to confirm they are not rewritten.
+///
+/// Temporary files are deleted if dropped without commit.
+#[derive(Debug, Default)]
+pub struct FileTransaction {
+    bup_number: Option<u32>,
+    files: Vec<TxFile>,
+    backups: Vec<String>,
+    #[cfg(test)]
+    fail_at: Option<(TxStep, usize)>,
+}
+
+impl FileTransaction {
+
+    /// Begin transaction, originals are backed up with `bup_number` if some
+    pub fn new(bup_number: Option<u32>) -> Self {
+        let mut tx = Self::default();
+        tx.bup_number = bup_number;
+        tx
+    }
+
+    /// Add file to replace, return temporary filename to write
+    pub fn add(&mut self, filename: &str) -> String {
+        let tmp_filename = make_tmp_filename(filename);
+        self.files.push(TxFile {
+            filename: filename.to_string(),
+            tmp_filename: tmp_filename.clone(),
+            old_filename: format!("{}.old", filename),
+            stamp: file_stamp(filename),
+            written_hash: 0,
+            is_moved: false,
+            is_replaced: false,
+        });
+        tmp_filename
+    }
+
+    /// Replace all files, return created backup filenames
+    pub fn commit(&mut self) -> Result<Vec<String>, i32> {
+        if let Err(e) = self.try_commit() {
+            self.rollback();
+            return Err(e);
+        }
+        for f in self.files.iter_mut() {
+            if f.is_moved { delete_file(&f.old_filename); }
+            f.is_moved = false;
+        }
+        Ok(std::mem::take(&mut self.backups))
+    }
+
+    /// Check replaced files have written contents
+    pub fn verify(&self) -> Result<(), i32> {
+        for f in self.files.iter().filter(|f| f.is_replaced) {
+            if file_hash(&f.filename) != Some(f.written_hash) {
+                return Err(_ERR_VERIFY_FAILED);
+            }
+        }
+        Ok(())
+    }
+
+    fn try_commit(&mut self) -> Result<(), i32> {
+        for idx in 0..self.files.len() {
+            if self.is_fail(TxStep::Prepare, idx) || !Path::new(&self.files[idx].tmp_filename).is_file() {
+                return Err(_ERR_CREATE_FILE);
+            }
+        }
+        for idx in 0..self.files.len() {
+            let f = &self.files[idx];
+            if self.is_fail(TxStep::Check, idx) || file_stamp(&f.filename) != f.stamp {
+                log_warn(&format!("{} was changed by other program.", f.filename));
+                return Err(_ERR_FILE_CHANGED);
+            }
+        }
+        if let Some(n) = self.bup_number {
+            for idx in 0..self.files.len() {
+                if !Path::new(&self.files[idx].filename).is_file() { continue; }
+                if self.is_fail(TxStep::Backup, idx) { return Err(_ERR_FILE_BACKUP); }
+                self.backups.push(file_backup(&self.files[idx].filename, n)?);
+            }
+        }
+        for idx in 0..self.files.len() {
+            if !Path::new(&self.files[idx].filename).is_file() { continue; }
+            let f = &self.files[idx];
+            if self.is_fail(TxStep::Move, idx) || rename(&f.filename, &f.old_filename).is_err() {
+                return Err(_ERR_FIX_FILE_COPY);
+            }
+            self.files[idx].is_moved = true;
+        }
+        for idx in 0..self.files.len() {
+            let f = &self.files[idx];
+            let hash = file_hash(&f.tmp_filename);
+            if self.is_fail(TxStep::Replace, idx) || rename(&f.tmp_filename, &f.filename).is_err() {
+                return Err(_ERR_FIX_FILE_COPY);
+            }
+            self.files[idx].written_hash = hash.unwrap_or_default();
+            self.files[idx].is_replaced = true;
+        }
+        Ok(())
+    }
+
+    /// Put back originals, delete temporary files and backups of this run
+    fn rollback(&mut self) {
+        for f in self.files.iter_mut().rev() {
+            if f.is_replaced {
+                delete_file(&f.filename);
+                f.is_replaced = false;
+            }
+            if f.is_moved {
+                if rename(&f.old_filename, &f.filename).is_err() {
+                    log_error(&format!("Rollback failed, original is left in {}", f.old_filename));
+                }
+                f.is_moved = false;
+            }
+            delete_file(&f.tmp_filename);
+        }
+        for bup_filename in self.backups.drain(..) {
+            delete_file(&bup_filename);
+        }
+    }
+
+    #[cfg(test)]
+    fn is_fail(&self, step: TxStep, idx: usize) -> bool {
+        self.fail_at == Some((step, idx))
+    }
+
+    #[cfg(not(test))]
+    fn is_fail(&self, _step: TxStep, _idx: usize) -> bool {
+        false
+    }
+}
+
+impl Drop for FileTransaction {
+    fn drop(&mut self) {
+        for f in self.files.iter().filter(|f| !f.is_replaced) {
+            delete_file(&f.tmp_filename);
+        }
+    }
+}
+
+/// Return size and modified time of file, None if not exists
+fn file_stamp(filename: &str) -> Option<(u64, SystemTime)> {
+    let meta = metadata(filename).ok()?;
+    Some((meta.len(), meta.modified().ok()?))
+}
+
+/// Return hash of file contents
+fn file_hash(filename: &str) -> Option<u64> {
+    let bytes = std::fs::read(filename).ok()?;
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    Some(hasher.finish())
+}
+
 /// Return file list on same file path
 pub fn get_filelist_same_dir(filename: &str) -> Option<Vec<String>> {
     // Get Dirs
@@ -37,6 +244,7 @@ pub fn get_filelist_same_dir(filename: &str) -> Option<Vec<String>> {
         Some(p) =>  p.to_str().map_or("", |s| s),
         _ => { return None; },
     };
+    let path = if path.is_empty() { "." } else { path };
     let dirs = match read_dir(path) {
         Ok(d) => d,
         _ => { return None; },
@@ -57,29 +265,48 @@ pub fn get_filelist_same_dir(filename: &str) -> Option<Vec<String>> {
     Some(files)
 }
 
-/// Return made new self backup file name
-pub fn get_new_bup_filename(filename: &str) -> String {
-    let mut last_number = 0;
-    let files = match get_filelist_same_dir(&filename) {
-        Some(v) =>  v,
-        _ => { return format!("{}.bup0001", &filename); },
+/// Return filename + ".bup0001"
+pub fn make_bup_filename(filename: &str, bup_number: u32) -> String {
+    format!("{}.bup{:04}", filename, bup_number)
+}
+
+/// Return numbers of existing backup files, in ascending order
+pub fn get_bup_numbers(filename: &str) -> Vec<u32> {
+    let Some(name) = Path::new(filename).file_name().and_then(|s| s.to_str()) else {
+        return Vec::new();
     };
-    // Detect last number
-    let escaped_filename = regex::escape(&filename);
-    let pat = format!(r"{}.bup([0-9]{{4}})+", &escaped_filename);
-    let re = Regex::new(&pat).unwrap();
-    for one_filename in files {
-        let cap = re.captures(&one_filename);
-        if let Some(c) = cap {
-            let one_number = c.get(1).map_or("", |m| m.as_str());
-            let one_number: i32 = one_number.parse().unwrap();
-            if last_number < one_number {
-                last_number = one_number;
-            };
-        };
+    let prefix = format!("{}.bup", name);
+    let mut numbers: Vec<u32> = get_filelist_same_dir(filename)
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|f| {
+            let number = Path::new(f).file_name()?.to_str()?.strip_prefix(&prefix)?;
+            if number.len() < 4 || !number.chars().all(|c| c.is_ascii_digit()) {
+                return None;
+            }
+            number.parse::<u32>().ok()
+        })
+        .collect();
+    numbers.sort_unstable();
+    numbers
+}
+
+/// Return next backup number of files written by one run
+///
+/// Files share the number, so they can be restored as a set.
+pub fn new_bup_number(filenames: &[&str]) -> u32 {
+    filenames.iter()
+        .filter_map(|f| get_bup_numbers(f).last().copied())
+        .max()
+        .unwrap_or(0) + 1
+}
+
+/// Return filename in same directory of other file
+pub fn same_dir_filename(filename: &str, name: &str) -> String {
+    match Path::new(filename).parent().and_then(|p| p.to_str()) {
+        Some(p) if !p.is_empty() => format!("{}{}{}", p, MAIN_SEPARATOR, name),
+        _ => name.to_string(),
     }
-    last_number += 1;
-    format!("{}.bup{:04}", &filename, last_number)
 }
 
 /// Check required files exists
@@ -87,5 +314,111 @@ pub fn is_exists_io_files(args: &Args) -> bool {
     if args.is_output_csv_agephone() {
         return Path::new(args.load_file_name()).is_file()
     }
-    Path::new(args.load_file_name()).is_file() && Path::new(args.save_file_name()).is_file()
+    // New file is allowed, except merging to it
+    let save = Path::new(args.save_file_name());
+    let is_save_ok = if args.is_merge() {
+        save.is_file()
+    } else {
+        save.parent().is_none_or(|d| d.as_os_str().is_empty() || d.is_dir())
+    };
+    Path::new(args.load_file_name()).is_file() && is_save_ok
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::{create_dir_all, read_to_string, remove_dir_all, write};
+    use std::io::Write;
+
+    /// Make files in new directory, return (directory, [xml, ini])
+    fn setup(name: &str) -> (String, [String; 2]) {
+        let dir = env::temp_dir().join(format!("vcf2msip_test_{}_{}", name, std::process::id()));
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        let xml = dir.join("Contacts.xml").to_str().unwrap().to_string();
+        let ini = dir.join("MicroSIP.ini").to_str().unwrap().to_string();
+        write(&xml, "old xml").unwrap();
+        write(&ini, "old ini").unwrap();
+        (dir.to_str().unwrap().to_string(), [xml, ini])
+    }
+
+    fn files_in(dir: &str) -> Vec<String> {
+        let mut files: Vec<String> = read_dir(dir).unwrap()
+            .map(|e| e.unwrap().file_name().to_str().unwrap().to_string())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_transaction_commit() {
+        let (dir, [xml, ini]) = setup("tx_commit");
+        let mut tx = FileTransaction::new(Some(1));
+        write(tx.add(&xml), "new xml").unwrap();
+        write(tx.add(&ini), "new ini").unwrap();
+        assert_eq!(2, tx.commit().unwrap().len());
+        assert_eq!(Ok(()), tx.verify());
+        assert_eq!("new xml", read_to_string(&xml).unwrap());
+        assert_eq!("new ini", read_to_string(&ini).unwrap());
+        assert_eq!("old xml", read_to_string(make_bup_filename(&xml, 1)).unwrap());
+        assert_eq!(
+            vec!["Contacts.xml", "Contacts.xml.bup0001", "MicroSIP.ini", "MicroSIP.ini.bup0001"],
+            files_in(&dir)
+        );
+        // Dropped without commit
+        let mut tx = FileTransaction::new(None);
+        write(tx.add(&xml), "newer xml").unwrap();
+        drop(tx);
+        assert_eq!("new xml", read_to_string(&xml).unwrap());
+        assert_eq!(4, files_in(&dir).len());
+        // Rewritten after commit
+        let mut tx = FileTransaction::new(None);
+        write(tx.add(&ini), "newer ini").unwrap();
+        tx.commit().unwrap();
+        write(&ini, "rewritten by MicroSIP").unwrap();
+        assert_eq!(Err(_ERR_VERIFY_FAILED), tx.verify());
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_transaction_rollback() {
+        let steps = [TxStep::Prepare, TxStep::Check, TxStep::Backup, TxStep::Move, TxStep::Replace];
+        for step in steps {
+            for idx in 0..2 {
+                for bup_number in [Some(1), None] {
+                    let (dir, [xml, ini]) = setup("tx_rollback");
+                    let mut tx = FileTransaction::new(bup_number);
+                    write(tx.add(&xml), "new xml").unwrap();
+                    write(tx.add(&ini), "new ini").unwrap();
+                    tx.fail_at = Some((step, idx));
+                    let res = tx.commit();
+                    if step == TxStep::Backup && bup_number.is_none() {
+                        assert!(res.is_ok());
+                        continue;
+                    }
+                    assert!(res.is_err(), "{:?} {}", step, idx);
+                    assert_eq!("old xml", read_to_string(&xml).unwrap(), "{:?} {}", step, idx);
+                    assert_eq!("old ini", read_to_string(&ini).unwrap(), "{:?} {}", step, idx);
+                    assert_eq!(vec!["Contacts.xml", "MicroSIP.ini"], files_in(&dir), "{:?} {}", step, idx);
+                }
+            }
+        }
+        // Original not exists yet
+        let (dir, [xml, ini]) = setup("tx_rollback");
+        delete_file(&ini);
+        let mut tx = FileTransaction::new(Some(1));
+        write(tx.add(&xml), "new xml").unwrap();
+        write(tx.add(&ini), "new ini").unwrap();
+        tx.fail_at = Some((TxStep::Replace, 1));
+        assert!(tx.commit().is_err());
+        assert_eq!(vec!["Contacts.xml"], files_in(&dir));
+        // Original is changed after added
+        let mut tx = FileTransaction::new(Some(1));
+        write(tx.add(&xml), "new xml").unwrap();
+        std::fs::OpenOptions::new().append(true).open(&xml).unwrap().write_all(b" changed").unwrap();
+        assert_eq!(Err(_ERR_FILE_CHANGED), tx.commit());
+        assert_eq!("old xml changed", read_to_string(&xml).unwrap());
+        assert_eq!(vec!["Contacts.xml"], files_in(&dir));
+        remove_dir_all(&dir).unwrap();
+    }
 }
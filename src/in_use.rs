@@ -0,0 +1,131 @@
+use crate::error_flg;
+use crate::logger;
+
+use std::fs::OpenOptions;
+use std::io::{Error, ErrorKind};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use error_flg::*;
+use logger::*;
+
+const MICROSIP_EXE: &str = "microsip.exe";
+const WAIT_INTERVAL: Duration = Duration::from_secs(1);
+/// Windows ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION
+const WIN_LOCK_ERRORS: &[i32] = &[32, 33];
+
+/// Check process name is MicroSIP, on Wine it's "microsip.exe" too
+fn is_microsip_name(name: &str) -> bool {
+    let name = name.trim().rsplit(['\\', '/']).next().unwrap_or_default();
+    name.eq_ignore_ascii_case(MICROSIP_EXE)
+}
+
+/// Check MicroSIP is running
+#[cfg(windows)]
+pub fn is_microsip_running() -> bool {
+    use std::process::Command;
+    let filter = format!("IMAGENAME eq {}", MICROSIP_EXE);
+    match Command::new("tasklist").args(["/FI", filter.as_str(), "/FO", "CSV", "/NH"]).output() {
+        Ok(out) => String::from_utf8_lossy(&out.stdout)
+            .lines()
+            .any(|l| l.split(',').next().is_some_and(|n| is_microsip_name(n.trim_matches('"')))),
+        Err(_) => false,
+    }
+}
+
+/// Check MicroSIP is running under Wine
+#[cfg(target_os = "linux")]
+pub fn is_microsip_running() -> bool {
+    let Ok(dirs) = std::fs::read_dir("/proc") else { return false; };
+    dirs.flatten().any(|entry| {
+        let path = entry.path();
+        let comm = std::fs::read_to_string(path.join("comm")).unwrap_or_default();
+        let cmdline = std::fs::read_to_string(path.join("cmdline")).unwrap_or_default();
+        is_microsip_name(&comm) || is_microsip_name(cmdline.split('\0').next().unwrap_or_default())
+    })
+}
+
+/// Check MicroSIP is running, by process list of macOS and BSD
+#[cfg(not(any(windows, target_os = "linux")))]
+pub fn is_microsip_running() -> bool {
+    use std::process::Command;
+    match Command::new("ps").args(["-A", "-o", "comm="]).output() {
+        Ok(out) => String::from_utf8_lossy(&out.stdout).lines().any(is_microsip_name),
+        Err(_) => false,
+    }
+}
+
+/// Check error of opening file is a sharing or lock violation
+fn is_lock_error(err: &Error) -> bool {
+    (cfg!(windows) && err.raw_os_error().is_some_and(|code| WIN_LOCK_ERRORS.contains(&code)))
+        || err.kind() == ErrorKind::ResourceBusy
+}
+
+/// Check file is opened by other program, Windows denies to open shared file
+///
+/// Other errors like read-only file or permission are returned as they are.
+pub fn is_file_locked(filename: &str) -> Result<bool, i32> {
+    if !std::path::Path::new(filename).is_file() {
+        return Ok(false);
+    }
+    match OpenOptions::new().append(true).open(filename) {
+        Ok(_) => Ok(false),
+        Err(e) if is_lock_error(&e) => Ok(true),
+        Err(e) => {
+            log_warn(&format!("{}: {}", filename, e));
+            Err(_ERR_WRITE_FILE)
+        },
+    }
+}
+
+/// Wait until MicroSIP is closed and files are not in use
+///
+/// Refuse at once if `wait_secs` is 0. `is_force` skips checking.
+pub fn wait_files_free(filenames: &[&str], wait_secs: u64, is_force: bool) -> Result<(), i32> {
+    if is_force {
+        return Ok(());
+    }
+    let start = Instant::now();
+    let mut is_logged = false;
+    loop {
+        let mut busy = is_microsip_running().then(|| "MicroSIP is running".to_string());
+        if busy.is_none() {
+            for filename in filenames {
+                if is_file_locked(filename)? {
+                    busy = Some(format!("{} is in use", filename));
+                    break;
+                }
+            }
+        }
+        let Some(busy) = busy else {
+            return Ok(());
+        };
+        if start.elapsed().as_secs() >= wait_secs {
+            log_warn(&busy);
+            return Err(_ERR_MICROSIP_RUNNING);
+        }
+        if !is_logged {
+            log_info(&format!("{}, waiting up to {} seconds to be closed.", busy, wait_secs));
+            is_logged = true;
+        }
+        sleep(WAIT_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_microsip_name() {
+        assert!(is_microsip_name("MicroSIP.exe"));
+        assert!(is_microsip_name("C:\\Program Files\\MicroSIP\\microsip.exe\n"));
+        assert!(is_microsip_name("/home/u/.wine/drive_c/MicroSIP/MicroSIP.exe"));
+        assert!(!is_microsip_name("vcf2msip.exe"));
+        assert!(!is_microsip_name(""));
+        assert_eq!(Ok(false), is_file_locked("testfiles/not_exists.xml"));
+        assert_eq!(Ok(false), is_file_locked("testfiles/test.xml"));
+        assert!(is_lock_error(&Error::from(ErrorKind::ResourceBusy)));
+        assert!(!is_lock_error(&Error::from(ErrorKind::PermissionDenied)));
+        assert_eq!(cfg!(windows), is_lock_error(&Error::from_raw_os_error(32)));
+    }
+}
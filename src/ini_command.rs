@@ -0,0 +1,137 @@
+use crate::error_flg;
+use crate::arg_and_help;
+use crate::file_fns;
+use crate::ini_io;
+use crate::in_use;
+
+use error_flg::*;
+use arg_and_help::*;
+use file_fns::*;
+use ini_io::*;
+use in_use::*;
+
+const ACCOUNT_KEYS: &[&str] = &["label", "server", "proxy", "domain", "username", "displayName"];
+
+/// Process of "ini" subcommand
+pub fn run_ini_command(args: &Args) -> Result<(), i32> {
+    if args.microsip_ini_file().is_empty() {
+        return Err(_ERR_MICROSIP_NOT_FOUND);
+    }
+    if !Path::new(args.microsip_ini_file()).is_file() {
+        return Err(_ERR_FILE_NOT_FOUND);
+    }
+    let sub_args = args.sub_args();
+    // Begin transaction before reading, to detect changes by other program
+    let ini_filename = args.microsip_ini_file();
+    let xml_filename = same_dir_filename(ini_filename, CONTACTS_XML_FILE);
+    let is_write = matches!(sub_args[0].as_str(), "set" | "unset");
+    if is_write {
+        wait_files_free(&[ini_filename], args.wait_secs(), args.is_force())?;
+    }
+    let bup_number = (is_write && !args.is_no_bup()).then(|| new_bup_number(&[ini_filename, &xml_filename]));
+    let mut tx = FileTransaction::new(bup_number);
+    let ini_tmp_filename = if is_write { tx.add(ini_filename) } else { String::new() };
+    let mut ini_io = IniIo::new(ini_filename)?;
+    match sub_args[0].as_str() {
+        "get" => {
+            let (section, key) = split_section_key(&sub_args[1])?;
+            match ini_io.get(section, key) {
+                Some(value) => println!("{}", value),
+                None => { return Err(_ERR_INI_KEY_NOT_FOUND); },
+            }
+        },
+        "list" => {
+            for line in account_lines(&ini_io) {
+                println!("{}", line);
+            }
+        },
+        "set" => {
+            for kv in sub_args[1..].chunks_exact(2) {
+                let (section, key) = split_section_key(&kv[0])?;
+                ini_io.set(section, key, &kv[1]);
+            }
+            save_ini(&ini_io, &mut tx, &ini_tmp_filename)?;
+        },
+        "unset" => {
+            let (section, key) = split_section_key(&sub_args[1])?;
+            if !ini_io.remove(section, key) {
+                return Err(_ERR_INI_KEY_NOT_FOUND);
+            }
+            save_ini(&ini_io, &mut tx, &ini_tmp_filename)?;
+        },
+        _ => unreachable!("action is checked at Args"),
+    }
+    Ok(())
+}
+
+/// Split "Account1.server" to ("Account1", "server")
+fn split_section_key(section_key: &str) -> Result<(&str, &str), i32> {
+    match section_key.split_once('.') {
+        Some((section, key)) if !section.is_empty() && !key.is_empty() => Ok((section, key)),
+        _ => Err(_ERR_INI_INVALID_KEY),
+    }
+}
+
+/// Return one line per account, active one is marked by "*"
+fn account_lines(ini_io: &IniIo) -> Vec<String> {
+    let active = ini_io.get("Settings", "accountId").unwrap_or_default();
+    let mut lines: Vec<String> = Vec::new();
+    for section in ini_io.section_names() {
+        let Some(id) = section.strip_prefix("Account") else { continue; };
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_digit()) { continue; }
+        let values: Vec<String> = ACCOUNT_KEYS
+            .iter()
+            .filter_map(|key| ini_io.get(section, key).map(|v| format!("{}={}", key, v)))
+            .collect();
+        let mark = if id == active { "*" } else { " " };
+        lines.push(format!("{}{}: {}", mark, section, values.join(", ")));
+    }
+    lines
+}
+
+/// Save ini to temporary file of transaction, then replace with backup
+fn save_ini(ini_io: &IniIo, tx: &mut FileTransaction, ini_tmp_filename: &str) -> Result<(), i32> {
+    ini_io.save(ini_tmp_filename)?;
+    tx.commit()?;
+    tx.verify()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_account_lines() {
+        let ini = IniIo::from_text(
+            "[Settings]\r\naccountId=2\r\n[Account1]\r\nlabel=Desk\r\nserver=pbx.local\r\n[Account2]\r\nserver=pbx2.local\r\nusername=201\r\n[AccountX]\r\n"
+        );
+        assert_eq!(
+            vec![
+                " Account1: label=Desk, server=pbx.local".to_string(),
+                "*Account2: server=pbx2.local, username=201".to_string(),
+            ],
+            account_lines(&ini)
+        );
+        assert_eq!(Ok(("Account1", "server")), split_section_key("Account1.server"));
+        assert_eq!(Err(_ERR_INI_INVALID_KEY), split_section_key("server"));
+    }
+
+    #[test]
+    fn test_save_ini_changed() {
+        let dir = env::temp_dir().join(format!("vcf2msip_test_ini_command_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let ini_filename = dir.join(INI_DEFAULT_FILE).to_str().unwrap().to_string();
+        std::fs::write(&ini_filename, "[Settings]\r\nenableLog=0\r\n").unwrap();
+        let mut tx = FileTransaction::new(None);
+        let ini_tmp_filename = tx.add(&ini_filename);
+        let mut ini_io = IniIo::new(&ini_filename).unwrap();
+        // Changed by MicroSIP after read, not overwritten
+        std::fs::write(&ini_filename, "[Settings]\r\nenableLog=0\r\naccountId=1\r\n").unwrap();
+        ini_io.set("Settings", "enableLog", "1");
+        assert_eq!(Err(_ERR_FILE_CHANGED), save_ini(&ini_io, &mut tx, &ini_tmp_filename));
+        drop(tx);
+        assert_eq!("[Settings]\r\nenableLog=0\r\naccountId=1\r\n", std::fs::read_to_string(&ini_filename).unwrap());
+        assert!(!Path::new(&ini_tmp_filename).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
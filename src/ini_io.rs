@@ -1,79 +1,393 @@
+#![allow(dead_code)]
+
 use crate::error_flg;
 use crate::vcf_parser;
+use crate::call_log;
 
-use std::io::BufWriter;
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
 use error_flg::*;
 use vcf_parser::*;
-use file_utils::write::Write as fu_write;
+use call_log::*;
+
+pub const INI_SECTION_CALLS: &str = "Calls";
+pub const INI_SECTION_SETTINGS: &str = "Settings";
+pub const INI_SECTION_SHORTCUTS: &str = "Shortcuts";
+
+/// Text encoding of ini file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IniEncoding {
+    Utf16Le,
+    Utf16Be,
+    Utf8,
+}
+
+/// One line of ini file
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct IniLine {
+    key: Option<String>, // None for comment, blank or unknown line
+    value: String,
+    raw: String,
+    eol: String,
+}
+
+/// One section of ini file, first one has no header
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct IniSection {
+    name: String,
+    header: Option<IniLine>,
+    lines: Vec<IniLine>,
+}
 
 #[derive(Debug)]
 pub struct IniIo {
-    data: String,
+    encoding: IniEncoding,
+    has_bom: bool,
+    eol: String,
+    sections: Vec<IniSection>,
+    calls_index: Option<CallsIndex>,
+}
+
+/// Index of [Calls] section lines by number key
+#[derive(Debug)]
+struct CallsIndex {
+    section: usize,
+    lines: HashMap<String, Vec<usize>>,
+}
+
+impl IniLine {
+
+    /// Parse one line with end of line
+    fn parse(raw_line: &str) -> Self {
+        let raw = raw_line.trim_end_matches(['\r', '\n']);
+        let eol = raw_line[raw.len()..].to_string();
+        let trimmed = raw.trim();
+        let kv = if trimmed.is_empty() || trimmed.starts_with(';') || trimmed.starts_with('#') {
+            None
+        } else {
+            raw.split_once('=')
+        };
+        match kv {
+            Some((k, v)) => Self { key: Some(k.trim().to_string()), value: v.to_string(), raw: raw.to_string(), eol },
+            None => Self { key: None, value: String::new(), raw: raw.to_string(), eol },
+        }
+    }
+
+    /// Make new key value line
+    fn entry(key: &str, value: &str, eol: &str) -> Self {
+        Self {
+            key: Some(key.to_string()),
+            value: value.to_string(),
+            raw: format!("{}={}", key, value),
+            eol: eol.to_string(),
+        }
+    }
+
+    fn is_key(&self, key: &str) -> bool {
+        self.key.as_ref().is_some_and(|k| k.eq_ignore_ascii_case(key))
+    }
+
+    fn set_value(&mut self, value: &str) {
+        let key = self.key.clone().unwrap_or_default();
+        *self = Self::entry(&key, value, &self.eol);
+    }
+
+    /// Set whole line, for the value including '=' like a call entry
+    fn set_raw(&mut self, raw: &str) {
+        let eol = self.eol.clone();
+        *self = Self::parse(raw);
+        self.eol = eol;
+    }
+}
+
+impl IniSection {
+
+    /// Section header line
+    fn header_name(raw_line: &str) -> Option<String> {
+        let trimmed = raw_line.trim().trim_start_matches('\u{feff}');
+        if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            Some(trimmed[1..trimmed.len() - 1].trim().to_string())
+        } else {
+            None
+        }
+    }
+
+    fn is_name(&self, name: &str) -> bool {
+        self.header.is_some() && self.name.eq_ignore_ascii_case(name)
+    }
 }
 
 impl IniIo {
-    /// Read .ini file (utf16bom) to string
+
+    /// Read .ini file (utf16 or utf8, BOM detected) to sections
     pub fn new(filename: &str) -> Result<Self, i32> {
-        let hfile = match File::open(&filename) {
+        let hfile = match File::open(filename) {
             Ok(h) => h,
             _ => return Err(_ERR_FILE_NOT_FOUND),
         };
         let mut reader = BufReader::new(&hfile);
         let mut ini_byte: Vec<u8> = Vec::new();
-        if let Err(_) = reader.read_to_end(&mut ini_byte) {
+        if reader.read_to_end(&mut ini_byte).is_err() {
             return Err(_ERR_READ_FILE);
         }
-        let ini_vec_u16: Vec<u16> = ini_byte
-            .chunks_exact(2)
-            .into_iter()
-            .map(|a| u16::from_ne_bytes([a[0], a[1]]))
-            .collect();
-        // convert to utf8 string
-        let ini_slice = ini_vec_u16.as_slice();
-        let data = String::from_utf16_lossy(ini_slice);
-        Ok(Self { data })
-    }
-
-    /// Return lines in match number from ini
-    pub fn get_match_number_lines(&self, number: &str) -> Vec<String> {
-        let pat = format!(r"(?m)^[\d]+={};.*$", fix_number(&number));
-        let re = Regex::new(&pat).unwrap();
-        let mut number_lines: Vec<String> = Vec::new();
-        for cap in re.captures_iter(&self.data) {
-            number_lines.push(cap[0].trim_end().to_string());
-        }
-        number_lines
-    }
-
-    /// Return renewed line string
-    pub fn make_new_number_line(old_line: &str, new_name: &str) -> String {
-        let v: Vec<&str> = old_line.split(';').collect();
-        if v.len() != 6 { return "".to_string(); };
-        let fix_new_name = new_name.replace(";", "");
-        let new_line = format!(
-            "{};{};{};{};{};{}", v[0], fix_new_name, v[2], v[3], v[4], v[5],
-        );
-        new_line
+        Ok(Self::from_bytes(&ini_byte))
+    }
+
+    /// Parse ini from raw bytes
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        let (encoding, has_bom) = Self::detect_encoding(bytes);
+        let body = if has_bom {
+            &bytes[if encoding == IniEncoding::Utf8 { 3 } else { 2 }..]
+        } else {
+            bytes
+        };
+        let text = match encoding {
+            IniEncoding::Utf8 => String::from_utf8_lossy(body).to_string(),
+            IniEncoding::Utf16Le | IniEncoding::Utf16Be => {
+                let vec_u16: Vec<u16> = body
+                    .chunks_exact(2)
+                    .map(|a| if encoding == IniEncoding::Utf16Le {
+                        u16::from_le_bytes([a[0], a[1]])
+                    } else {
+                        u16::from_be_bytes([a[0], a[1]])
+                    })
+                    .collect();
+                String::from_utf16_lossy(&vec_u16)
+            },
+        };
+        let mut ini = Self::from_text(&text);
+        ini.encoding = encoding;
+        ini.has_bom = has_bom;
+        ini
+    }
+
+    /// Parse ini from text, saved as UTF-16LE with BOM like MicroSIP
+    pub fn from_text(text: &str) -> Self {
+        let eol = match text.find('\n') {
+            Some(p) if p > 0 && text.as_bytes()[p - 1] == b'\r' => "\r\n",
+            Some(_) => "\n",
+            None => "\r\n",
+        };
+        let mut sections = vec![IniSection { name: String::new(), header: None, lines: Vec::new() }];
+        for raw_line in text.split_inclusive('\n') {
+            if let Some(name) = IniSection::header_name(raw_line) {
+                sections.push(IniSection { name, header: Some(IniLine::parse(raw_line)), lines: Vec::new() });
+            } else if let Some(section) = sections.last_mut() {
+                section.lines.push(IniLine::parse(raw_line));
+            }
+        }
+        Self { encoding: IniEncoding::Utf16Le, has_bom: true, eol: eol.to_string(), sections, calls_index: None }
+    }
+
+    /// Detect encoding by BOM, or by NUL bytes
+    fn detect_encoding(bytes: &[u8]) -> (IniEncoding, bool) {
+        if bytes.starts_with(&[0xFF, 0xFE]) {
+            (IniEncoding::Utf16Le, true)
+        } else if bytes.starts_with(&[0xFE, 0xFF]) {
+            (IniEncoding::Utf16Be, true)
+        } else if bytes.starts_with(&[0xEF, 0xBB, 0xBF]) {
+            (IniEncoding::Utf8, true)
+        } else if bytes.len() >= 2 && bytes[1] == 0 && bytes[0] != 0 {
+            (IniEncoding::Utf16Le, false)
+        } else if bytes.len() >= 2 && bytes[0] == 0 && bytes[1] != 0 {
+            (IniEncoding::Utf16Be, false)
+        } else {
+            (IniEncoding::Utf8, false)
+        }
+    }
+
+    pub fn encoding(&self) -> IniEncoding {
+        self.encoding
+    }
+
+    /// Return whole text, without BOM
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for section in &self.sections {
+            for line in section.header.iter().chain(section.lines.iter()) {
+                text.push_str(&line.raw);
+                text.push_str(&line.eol);
+            }
+        }
+        text
+    }
+
+    /// Return section names in file order
+    pub fn section_names(&self) -> Vec<&str> {
+        self.sections.iter().filter(|s| s.header.is_some()).map(|s| s.name.as_str()).collect()
     }
 
-    /// Replace ini string
-    pub fn replace(&mut self, old_line: &str, new_line: &str) {
-        self.data = self.data.replace(old_line, new_line);
+    /// Return key and value pairs in section
+    pub fn entries(&self, section: &str) -> Vec<(&str, &str)> {
+        self.section(section).map_or(Vec::new(), |s| {
+            s.lines.iter()
+                .filter_map(|l| l.key.as_deref().map(|k| (k, l.value.as_str())))
+                .collect()
+        })
+    }
+
+    /// Return value of key in section
+    pub fn get(&self, section: &str, key: &str) -> Option<&str> {
+        self.section(section)?
+            .lines.iter()
+            .find(|l| l.is_key(key))
+            .map(|l| l.value.as_str())
+    }
+
+    /// Set value of key in section, add key and section if not exist
+    pub fn set(&mut self, section: &str, key: &str, value: &str) {
+        self.calls_index = None;
+        let eol = self.eol.clone();
+        let has_final_eol = self.ends_with_eol();
+        let idx = match self.sections.iter().position(|s| s.is_name(section)) {
+            Some(idx) => idx,
+            None => {
+                self.terminate_last_line();
+                self.sections.push(IniSection {
+                    name: section.to_string(),
+                    header: Some(IniLine { key: None, value: String::new(), raw: format!("[{}]", section), eol: eol.clone() }),
+                    lines: Vec::new(),
+                });
+                self.sections.len() - 1
+            },
+        };
+        let is_last_section = idx + 1 == self.sections.len();
+        let sect = &mut self.sections[idx];
+        if let Some(line) = sect.lines.iter_mut().find(|l| l.is_key(key)) {
+            line.set_value(value);
+            return;
+        }
+        // Add after last entry, before trailing blank lines
+        let pos = sect.lines.iter().rposition(|l| !l.raw.trim().is_empty()).map_or(0, |p| p + 1);
+        let is_file_end = pos == sect.lines.len() && is_last_section;
+        let prev_line = if pos > 0 { sect.lines.get_mut(pos - 1) } else { sect.header.as_mut() };
+        if let Some(l) = prev_line {
+            if l.eol.is_empty() { l.eol = eol.clone(); }
+        }
+        let new_eol = if is_file_end && !has_final_eol { "" } else { &eol };
+        sect.lines.insert(pos, IniLine::entry(key, value, new_eol));
+    }
+
+    /// Remove key in section, return true if removed
+    pub fn remove(&mut self, section: &str, key: &str) -> bool {
+        self.calls_index = None;
+        let Some(sect) = self.sections.iter_mut().find(|s| s.is_name(section)) else {
+            return false;
+        };
+        let len = sect.lines.len();
+        sect.lines.retain(|l| !l.is_key(key));
+        len != sect.lines.len()
+    }
+
+    /// Remove all keys in section, comments are kept
+    pub fn clear_section(&mut self, section: &str) {
+        self.calls_index = None;
+        if let Some(sect) = self.sections.iter_mut().find(|s| s.is_name(section)) {
+            sect.lines.retain(|l| l.key.is_none());
+        }
+    }
+
+    fn section(&self, section: &str) -> Option<&IniSection> {
+        self.sections.iter().find(|s| s.is_name(section))
+    }
+
+    fn ends_with_eol(&self) -> bool {
+        let last = self.sections.last()
+            .and_then(|s| s.lines.last().or(s.header.as_ref()));
+        last.is_none_or(|l| !l.eol.is_empty())
+    }
+
+    /// Give end of line to last line, before add new line to end of file
+    fn terminate_last_line(&mut self) {
+        let eol = self.eol.clone();
+        if let Some(s) = self.sections.last_mut() {
+            if let Some(l) = s.lines.last_mut().or(s.header.as_mut()) {
+                if l.eol.is_empty() && !l.raw.is_empty() { l.eol = eol; }
+            }
+        }
+    }
+
+    /// Return lines in [Calls] section that could not be parsed
+    pub fn unparsed_calls(&self) -> Vec<String> {
+        self.section(INI_SECTION_CALLS).map_or(Vec::new(), |s| {
+            s.lines.iter()
+                .map(|l| l.raw.trim())
+                .filter(|l| !l.is_empty() && !l.starts_with(';') && CallEntry::parse(l).is_none())
+                .map(|l| l.to_string())
+                .collect()
+        })
+    }
+
+    /// Make index of [Calls] section by number key, for renaming many numbers
+    fn make_calls_index(&self) -> Option<CallsIndex> {
+        let section = self.sections.iter().position(|s| s.is_name(INI_SECTION_CALLS))?;
+        let mut lines: HashMap<String, Vec<usize>> = HashMap::new();
+        for (idx, line) in self.sections[section].lines.iter().enumerate() {
+            let Some(ce) = CallEntry::parse(&line.raw) else { continue; };
+            let key = number_key(ce.number());
+            if !key.is_empty() {
+                lines.entry(key).or_default().push(idx);
+            }
+        }
+        Some(CallsIndex { section, lines })
+    }
+
+    /// Rename call entries matched number in [Calls] section, return entries before renamed
+    pub fn rename_calls(&mut self, number: &str, new_name: &str) -> Vec<CallEntry> {
+        if self.calls_index.is_none() {
+            self.calls_index = self.make_calls_index();
+        }
+        let Some(index) = &self.calls_index else {
+            return Vec::new();
+        };
+        let Some(line_idxs) = index.lines.get(&number_key(number)) else {
+            return Vec::new();
+        };
+        let sect = &mut self.sections[index.section];
+        let mut renamed: Vec<CallEntry> = Vec::with_capacity(line_idxs.len());
+        for idx in line_idxs {
+            let line = &mut sect.lines[*idx];
+            let Some(mut ce) = CallEntry::parse(&line.raw) else { continue; };
+            if !ce.is_match(number) { continue; }
+            renamed.push(ce.clone());
+            ce.set_name(new_name);
+            line.set_raw(&ce.to_line());
+        }
+        renamed
+    }
+
+    /// Return encoded bytes in original encoding
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let text = self.to_text();
+        let mut bytes: Vec<u8> = Vec::with_capacity(text.len() * 2 + 3);
+        match self.encoding {
+            IniEncoding::Utf8 => {
+                if self.has_bom { bytes.extend_from_slice(&[0xEF, 0xBB, 0xBF]); }
+                bytes.extend_from_slice(text.as_bytes());
+            },
+            IniEncoding::Utf16Le | IniEncoding::Utf16Be => {
+                let bom = if self.has_bom { Some(0xFEFF) } else { None };
+                for uu in bom.into_iter().chain(text.encode_utf16()) {
+                    if self.encoding == IniEncoding::Utf16Le {
+                        bytes.extend_from_slice(&uu.to_le_bytes());
+                    } else {
+                        bytes.extend_from_slice(&uu.to_be_bytes());
+                    }
+                }
+            },
+        }
+        bytes
     }
 
     /// Save ini file
     pub fn save(&self, filename: &str) -> Result<(), i32> {
-        let hfile = match File::create(&filename) {
+        let hfile = match File::create(filename) {
             Ok(h ) => h,
             _ => return Err(_ERR_FILE_NOT_FOUND),
         };
         let mut writer = BufWriter::new(&hfile);
-        // Write Utf16 encoded ini buffer
-        let vec_u16: Vec<u16> = self.data.encode_utf16().collect();
-        for uu in &vec_u16 {
-            if let Err(_) = writer.write_u16(*uu) {
-                return Err(_ERR_WRITE_INI_FILE);
-            };
+        if writer.write_all(&self.to_bytes()).is_err() || writer.flush().is_err() {
+            return Err(_ERR_WRITE_INI_FILE);
         }
         Ok(())
     }
@@ -82,53 +396,117 @@ impl IniIo {
 #[cfg(test)]
 mod test {
     use super::*;
-    const TEST_INI_FILENAME: &'static str = r".\testfiles\test.ini";
-    const TEST_STRING: &'static [&'static str]  = &[
-        "\u{feff}[Calls]\r\n0=112233;AAA;2;1111;0;cancel\r\n1=445566;BBB;2;2222;0;cancel\r\n2=778899;CCC;2;3333;0;cancel\r\n10=123456789;DDD;2;4000;0;cancel\r\n11=123456789;DDD;2;4001;0;cancel\r\n",
-        "\u{feff}[Calls]\r\n0=112233;AAA;2;1111;0;cancel\r\n1=445566;ZZZ;2;2222;0;cancel\r\n2=778899;CCC;2;3333;0;cancel\r\n10=123456789;DDD;2;4000;0;cancel\r\n11=123456789;DDD;2;4001;0;cancel\r\n"
-    ];
-    const TEST_LINE: &'static [&'static str] = &[
-        "1=445566;BBB;2;2222;0;cancel",
-        "1=445566;ZZZ;2;2222;0;cancel"
+    const TEST_INI_FILENAME: &str = "testfiles/test.ini";
+    const TEST_STRING: &[&str]  = &[
+        "[Calls]\r\n0=112233;AAA;2;1111;0;cancel\r\n1=445566;BBB;2;2222;0;cancel\r\n2=778899;CCC;2;3333;0;cancel\r\n10=123456789;DDD;2;4000;0;cancel\r\n11=123456789;DDD;2;4001;0;cancel\r\n",
+        "[Calls]\r\n0=112233;AAA;2;1111;0;cancel\r\n1=445566;ZZZ;2;2222;0;cancel\r\n2=778899;CCC;2;3333;0;cancel\r\n10=123456789;DDD;2;4000;0;cancel\r\n11=123456789;DDD;2;4001;0;cancel\r\n"
     ];
+    const TEST_SECTIONS: &str = "; comment\n[Settings]\nenableLog=0\n\n[Calls]\n0=445566;BBB\n[Account1]\nserver=old\n";
 
     fn test_switch(str: &str) -> usize {
-        if let None = str.find("ZZZ") {0} else {1}
+        if str.find("ZZZ").is_none() {0} else {1}
     }
 
     #[test]
     fn test_ini_read() {
         let ini = IniIo::new(TEST_INI_FILENAME).unwrap();
-        let sw = test_switch(&ini.data);
-        assert_eq!(TEST_STRING[sw], ini.data);
+        let sw = test_switch(&ini.to_text());
+        assert_eq!(TEST_STRING[sw], ini.to_text());
+        assert_eq!(IniEncoding::Utf16Le, ini.encoding());
+        assert_eq!(vec!["Calls"], ini.section_names());
     }
 
     #[test]
-    fn test_ini_get_line() {
-        let ini = IniIo::new(TEST_INI_FILENAME).unwrap();
-        let old_lines = ini.get_match_number_lines("123456789");
-        assert_eq!(2, old_lines.len());
+    fn test_ini_match_entries() {
+        let mut ini = IniIo::new(TEST_INI_FILENAME).unwrap();
+        assert_eq!(2, ini.rename_calls("123456789", "DDD").len());
+        assert!(ini.unparsed_calls().is_empty());
     }
 
     #[test]
-    fn test_ini_write_and_read() {
-        let mut ini = IniIo::new(TEST_INI_FILENAME).unwrap();
-        let sw = test_switch(&ini.data);
-        let old_lines = ini.get_match_number_lines("445566");
-        for old_line in old_lines {
-            let new_line = if sw == 0 {
-                IniIo::make_new_number_line(&old_line, "ZZZ")
-            } else {
-                IniIo::make_new_number_line(&old_line, "BBB")
-            };
+    fn test_ini_rename_section_only() {
+        let mut ini = IniIo::from_text(
+            "[Settings]\r\n0=445566;keep\r\n[Calls]\r\n0=sip:445566@pbx;BBB;2;1;0;\r\n1=broken\r\n2=+44-55-66;BBB"
+        );
+        assert_eq!(2, ini.rename_calls("44-55-66", "ZZZ").len());
+        assert_eq!(
+            "[Settings]\r\n0=445566;keep\r\n[Calls]\r\n0=sip:445566@pbx;ZZZ;2;1;0;\r\n1=broken\r\n2=+44-55-66;ZZZ",
+            ini.to_text()
+        );
+        assert_eq!(vec!["1=broken".to_string()], ini.unparsed_calls());
+        // Index is renewed after lines are changed
+        ini.remove("Calls", "0");
+        ini.set("Calls", "3", "445566;BBB");
+        assert_eq!(2, ini.rename_calls("445566", "YYY").len());
+        assert!(ini.to_text().ends_with("2=+44-55-66;YYY\r\n3=445566;YYY"));
+    }
+
+    #[test]
+    fn test_ini_rename_sip_domain() {
+        let mut ini = IniIo::from_text(
+            "[Calls]\r\n0=sip:201@pbx.local;A;2;1;0;\r\n1=sip:201@other.example;B;2;1;0;\r\n2=201;C;2;1;0;"
+        );
+        assert_eq!(2, ini.rename_calls("sip:201@PBX.local", "ZZZ").len());
+        assert_eq!(
+            "[Calls]\r\n0=sip:201@pbx.local;ZZZ;2;1;0;\r\n1=sip:201@other.example;B;2;1;0;\r\n2=201;ZZZ;2;1;0;",
+            ini.to_text()
+        );
+    }
 
-            let invsw = (sw as i32 * -1 + 1) as usize;
-            assert_eq!(TEST_LINE[invsw], new_line);
+    #[test]
+    fn test_ini_sections() {
+        let mut ini = IniIo::from_text(TEST_SECTIONS);
+        assert_eq!(vec!["Settings", "Calls", "Account1"], ini.section_names());
+        assert_eq!(Some("0"), ini.get("settings", "EnableLog"));
+        assert_eq!(None, ini.get("Calls", "enableLog"));
+        ini.set("Settings", "enableLog", "1");
+        ini.set("Settings", "denyIncoming", "all");
+        ini.set("Account1", "server", "pbx.local");
+        ini.set("Account2", "server", "pbx2.local");
+        assert!(ini.remove("Calls", "0"));
+        assert!(!ini.remove("Calls", "0"));
+        ini.clear_section("Account9");
+        ini.clear_section("settings");
+        ini.set("Settings", "enableLog", "1");
+        ini.set("Settings", "denyIncoming", "all");
+        assert_eq!(
+            "; comment\n[Settings]\nenableLog=1\ndenyIncoming=all\n\n[Calls]\n[Account1]\nserver=pbx.local\n[Account2]\nserver=pbx2.local\n",
+            ini.to_text()
+        );
+    }
 
-            ini.replace(&old_line, &new_line);
-            assert_eq!(TEST_STRING[invsw], ini.data);
+    #[test]
+    fn test_ini_encodings() {
+        let text = "[Calls]\r\n0=1;あ\r\n";
+        let le: Vec<u8> = [0xFF, 0xFE].into_iter()
+            .chain(text.encode_utf16().flat_map(|u| u.to_le_bytes()))
+            .collect();
+        let be: Vec<u8> = [0xFE, 0xFF].into_iter()
+            .chain(text.encode_utf16().flat_map(|u| u.to_be_bytes()))
+            .collect();
+        let u8bom: Vec<u8> = [0xEF, 0xBB, 0xBF].into_iter().chain(text.bytes()).collect();
+        for (bytes, encoding) in [
+            (le, IniEncoding::Utf16Le),
+            (be, IniEncoding::Utf16Be),
+            (u8bom, IniEncoding::Utf8),
+            (text.as_bytes().to_vec(), IniEncoding::Utf8),
+        ] {
+            let ini = IniIo::from_bytes(&bytes);
+            assert_eq!(encoding, ini.encoding());
+            assert_eq!(text, ini.to_text());
+            assert_eq!(bytes, ini.to_bytes());
         }
+    }
+
+    #[test]
+    fn test_ini_write_and_read() {
+        let mut ini = IniIo::new(TEST_INI_FILENAME).unwrap();
+        let sw = test_switch(&ini.to_text());
+        let new_name = if sw == 0 { "ZZZ" } else { "BBB" };
+        assert_eq!(1, ini.rename_calls("445566", new_name).len());
+        let invsw = (-(sw as i32) + 1) as usize;
+        assert_eq!(TEST_STRING[invsw], ini.to_text());
         ini.save(TEST_INI_FILENAME).unwrap();
         test_ini_read(); // assert renewed file
     }
-}
\ No newline at end of file
+}
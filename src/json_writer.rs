@@ -0,0 +1,91 @@
+use std::fmt;
+
+/// Minimal json value for reports
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(i64),
+    Str(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+
+    /// Make object from key and value pairs, in order
+    pub fn object(pairs: Vec<(&str, JsonValue)>) -> Self {
+        Self::Object(pairs.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+
+    pub fn str(s: &str) -> Self {
+        Self::Str(s.to_string())
+    }
+
+    pub fn num(n: usize) -> Self {
+        Self::Number(n as i64)
+    }
+}
+
+impl fmt::Display for JsonValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => write!(f, "null"),
+            Self::Bool(b) => write!(f, "{}", b),
+            Self::Number(n) => write!(f, "{}", n),
+            Self::Str(s) => write!(f, "\"{}\"", json_escape(s)),
+            Self::Array(v) => {
+                write!(f, "[")?;
+                for (i, value) in v.iter().enumerate() {
+                    if i > 0 { write!(f, ",")?; }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            },
+            Self::Object(v) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in v.iter().enumerate() {
+                    if i > 0 { write!(f, ",")?; }
+                    write!(f, "\"{}\":{}", json_escape(key), value)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+/// Escape string for json
+pub fn json_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_json_write() {
+        let json = JsonValue::object(vec![
+            ("name", JsonValue::str("太郎 \"T\"\r\n")),
+            ("count", JsonValue::num(3)),
+            ("ok", JsonValue::Bool(false)),
+            ("list", JsonValue::Array(vec![JsonValue::Null, JsonValue::str("\u{1}")])),
+        ]);
+        assert_eq!(
+            r#"{"name":"太郎 \"T\"\r\n","count":3,"ok":false,"list":[null,"\u0001"]}"#,
+            json.to_string()
+        );
+    }
+}
@@ -0,0 +1,195 @@
+use crate::vcf_parser;
+
+use std::collections::HashMap;
+use vcf_parser::*;
+
+pub const LDAP_FILTER_DEFAULT: &str = "(|(telephoneNumber=*)(mobile=*))";
+pub const LDAP_MAP_DEFAULT: &str = "name=cn,last=sn,first=givenName,org=department,tel.WORK=telephoneNumber,tel.CELL=mobile";
+
+/// Mapping from contact fields to LDAP attributes, like "name=cn,tel.WORK=telephoneNumber"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LdapMapping {
+    fields: Vec<(String, String)>, // field, attribute
+}
+
+impl Default for LdapMapping {
+    fn default() -> Self {
+        Self { fields: Vec::new() }.changed(LDAP_MAP_DEFAULT).unwrap()
+    }
+}
+
+impl LdapMapping {
+
+    /// Return default mapping changed by "field=attr,...", "field=" removes the field
+    ///
+    /// None if unknown field.
+    pub fn parse(text: &str) -> Option<Self> {
+        Self::default().changed(text)
+    }
+
+    fn changed(mut self, text: &str) -> Option<Self> {
+        for item in text.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+            let (field, attr) = item.split_once('=')?;
+            let (field, attr) = (field.trim(), attr.trim());
+            if !ContactParts::is_field(field) {
+                return None;
+            }
+            self.fields.retain(|(f, _)| f != field);
+            if !attr.is_empty() {
+                self.fields.push((field.to_string(), attr.to_string()));
+            }
+        }
+        Some(self)
+    }
+
+    /// Return attributes to search
+    pub fn attrs(&self) -> Vec<&str> {
+        self.fields.iter().map(|(_, attr)| attr.as_str()).collect()
+    }
+
+    /// Return vcard of LDAP entry
+    pub fn to_vcard(&self, entry: &HashMap<String, Vec<String>>) -> String {
+        let mut parts = ContactParts::default();
+        for (field, attr) in &self.fields {
+            for (_, values) in entry.iter().filter(|(k, _)| k.eq_ignore_ascii_case(attr)) {
+                for value in values {
+                    parts.set(field, value);
+                }
+            }
+        }
+        parts.to_vcard()
+    }
+}
+
+/// Query of LDAP directory
+#[derive(Debug, Clone, Default)]
+pub struct LdapQuery {
+    pub url: String,
+    pub base_dn: String,
+    pub filter: String,
+    pub bind_dn: String,
+    pub password: String,
+    pub mapping: LdapMapping,
+}
+
+/// Search LDAP and write entries to vcf file, return count of entries
+#[cfg(feature = "ldap")]
+pub fn search_to_vcf(query: &LdapQuery, vcf_filename: &str) -> Result<usize, i32> {
+    use crate::error_flg::*;
+    use crate::file_fns::FileTransaction;
+    use crate::logger::*;
+    use ldap3::adapters::{Adapter, EntriesOnly, PagedResults};
+    use ldap3::{LdapConn, LdapConnSettings, Scope, SearchEntry};
+    use std::time::Duration;
+
+    let settings = LdapConnSettings::new().set_conn_timeout(Duration::from_secs(30));
+    let mut conn = match LdapConn::with_settings(settings, &query.url) {
+        Ok(c) => c,
+        Err(e) => {
+            log_warn(&format!("{}: {}", query.url, e));
+            return Err(_ERR_LDAP_CONNECT);
+        },
+    };
+    if !query.bind_dn.is_empty() {
+        let res = conn.simple_bind(&query.bind_dn, &query.password).and_then(|r| r.success());
+        if let Err(e) = res {
+            log_warn(&format!("bind {}: {}", query.bind_dn, e));
+            return Err(_ERR_LDAP_BIND);
+        }
+    }
+    let adapters: Vec<Box<dyn Adapter<_, _>>> = vec![
+        Box::new(EntriesOnly::new()),
+        Box::new(PagedResults::new(500)),
+    ];
+    let attrs = query.mapping.attrs();
+    let mut text = String::new();
+    let mut count = 0;
+    let search = conn.streaming_search_with(adapters, &query.base_dn, Scope::Subtree, &query.filter, attrs);
+    let mut stream = match search {
+        Ok(s) => s,
+        Err(e) => {
+            log_warn(&format!("search {} {}: {}", query.base_dn, query.filter, e));
+            return Err(_ERR_LDAP_SEARCH);
+        },
+    };
+    loop {
+        match stream.next() {
+            Ok(Some(entry)) => {
+                let entry = SearchEntry::construct(entry);
+                log_record("LDAP", &[("dn", entry.dn.clone())]);
+                text.push_str(&query.mapping.to_vcard(&entry.attrs));
+                count += 1;
+            },
+            Ok(None) => break,
+            Err(e) => {
+                log_warn(&format!("search {} {}: {}", query.base_dn, query.filter, e));
+                return Err(_ERR_LDAP_SEARCH);
+            },
+        }
+    }
+    if let Err(e) = stream.result().success() {
+        log_warn(&format!("search {} {}: {}", query.base_dn, query.filter, e));
+        return Err(_ERR_LDAP_SEARCH);
+    }
+    let _ = conn.unbind();
+
+    let mut tx = FileTransaction::new(None);
+    if std::fs::write(tx.add(vcf_filename), text).is_err() {
+        return Err(_ERR_WRITE_FILE);
+    }
+    tx.commit()?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arg_and_help::ARG_PAT_DEFAULT;
+
+    fn entry(attrs: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+        attrs.iter().map(|(k, v)| (k.to_string(), v.iter().map(|s| s.to_string()).collect())).collect()
+    }
+
+    #[test]
+    fn test_to_vcard() {
+        let mapping = LdapMapping::default();
+        assert_eq!(vec!["cn", "sn", "givenName", "department", "telephoneNumber", "mobile"], mapping.attrs());
+        let vcard = mapping.to_vcard(&entry(&[
+            ("cn", &["Yamada Taro"]),
+            ("sn", &["Yamada"]),
+            ("givenname", &["Taro"]),
+            ("department", &["Sales"]),
+            ("telephoneNumber", &["03-1111-2222", "03-1111-3333"]),
+            ("mobile", &["090-1111-2222\n"]),
+        ]));
+        let ct = Contact::new(&vcard);
+        assert_eq!("Yamada Taro", ct.full_name());
+        let tels: Vec<(&str, &str)> = ct.tel_iter().map(|t| (t.teltype(), t.number())).collect();
+        assert_eq!(vec![("WORK", "03-1111-2222"), ("WORK", "03-1111-3333"), ("CELL", "090-1111-2222")], tels);
+        assert_eq!(
+            "Y - Yamada Taro (CELL)",
+            ct.fmt_name(ARG_PAT_DEFAULT, &ct.finitial(), &ct.linitial(), "CELL")
+        );
+        // No name, organization is used
+        let ct = Contact::new(&mapping.to_vcard(&entry(&[("department", &["Reception"]), ("telephoneNumber", &["100"])])));
+        assert_eq!("Reception", ct.full_name());
+    }
+
+    #[test]
+    fn test_parse_mapping() {
+        let mapping = LdapMapping::parse("name=displayName, tel.CELL=, tel.IPPHONE=ipPhone, categories=ou").unwrap();
+        assert_eq!(vec!["sn", "givenName", "department", "telephoneNumber", "displayName", "ipPhone", "ou"], mapping.attrs());
+        let ct = Contact::new(&mapping.to_vcard(&entry(&[
+            ("displayName", &["Suzuki"]),
+            ("ipPhone", &["201"]),
+            ("mobile", &["090-0000-0000"]),
+            ("ou", &["Dev", "SpeedDial"]),
+        ])));
+        assert_eq!("Suzuki", ct.full_name());
+        assert_eq!(vec!["201"], ct.tel_iter().map(|t| t.number()).collect::<Vec<&str>>());
+        assert!(ct.speed_dial_order("SpeedDial").is_some());
+        assert_eq!(None, LdapMapping::parse("mail=mail"));
+        assert_eq!(None, LdapMapping::parse("tel.WORK1=ipPhone"));
+        assert_eq!(None, LdapMapping::parse("cn"));
+    }
+}
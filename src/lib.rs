@@ -0,0 +1,25 @@
+pub mod vcf_parser;
+pub mod call_log;
+pub mod ini_io;
+pub mod error_flg;
+pub mod arg_and_help;
+pub mod file_fns;
+pub mod xml_parser;
+pub mod base64;
+pub mod progress_bar;
+pub mod sort_order;
+pub mod ini_command;
+pub mod json_writer;
+pub mod vcf_check;
+pub mod conv_report;
+pub mod logger;
+pub mod backups;
+pub mod in_use;
+pub mod msip_dir;
+#[cfg(feature = "carddav")]
+pub mod carddav;
+pub mod ldap_source;
+pub mod csv_source;
+pub mod phonebook_source;
+pub mod watch;
+pub mod review;
@@ -0,0 +1,112 @@
+use std::io::{stderr, IsTerminal};
+use std::sync::atomic::{AtomicU8, Ordering};
+use terminal_size::{terminal_size_of, Width};
+
+const CONSOLE_W_DEFAULT: usize = 80;
+
+/// Level of messages to stderr
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum LogLevel {
+    Quiet,
+    #[default]
+    Normal,
+    Verbose,
+}
+
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Normal as u8);
+
+pub fn set_log_level(level: LogLevel) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn log_level() -> LogLevel {
+    match LOG_LEVEL.load(Ordering::Relaxed) {
+        0 => LogLevel::Quiet,
+        1 => LogLevel::Normal,
+        _ => LogLevel::Verbose,
+    }
+}
+
+/// Print message, except quiet
+pub fn log_info(msg: &str) {
+    if log_level() >= LogLevel::Normal {
+        eprintln!("{}", msg);
+    }
+}
+
+/// Print warning, except quiet
+pub fn log_warn(msg: &str) {
+    if log_level() >= LogLevel::Normal {
+        eprintln!("WARNING: {}", msg);
+    }
+}
+
+/// Print error, always
+pub fn log_error(msg: &str) {
+    eprintln!("ERROR: {}", msg);
+}
+
+/// Print message, only verbose
+pub fn log_verbose(msg: &str) {
+    if log_level() >= LogLevel::Verbose {
+        eprintln!("{}", msg);
+    }
+}
+
+/// Print one record like `SKIP card=2 name="Taro Yamada"`, only verbose
+pub fn log_record(event: &str, fields: &[(&str, String)]) {
+    if log_level() >= LogLevel::Verbose {
+        eprintln!("{}", format_record(event, fields));
+    }
+}
+
+/// Return one line of "key=value" fields, value is quoted if needed
+fn format_record(event: &str, fields: &[(&str, String)]) -> String {
+    let mut line = event.to_string();
+    for (key, value) in fields {
+        let is_quote = value.is_empty() || value.chars().any(|c| c.is_whitespace() || matches!(c, '"' | '='));
+        if is_quote {
+            let escaped = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+            line.push_str(&format!(" {}=\"{}\"", key, escaped));
+        } else {
+            line.push_str(&format!(" {}={}", key, value));
+        }
+    }
+    line
+}
+
+/// Progress bar is drawn only to terminal, except quiet
+pub fn is_progress_visible() -> bool {
+    log_level() >= LogLevel::Normal && stderr().is_terminal()
+}
+
+/// Return columns of terminal, or COLUMNS env, or 80
+pub fn terminal_width() -> usize {
+    if let Some((Width(w), _)) = terminal_size_of(stderr()) {
+        return w as usize;
+    }
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|s| s.parse::<usize>().ok())
+        .filter(|w| *w > 0)
+        .unwrap_or(CONSOLE_W_DEFAULT)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_record() {
+        assert_eq!(
+            r#"SKIP card=2 line=10 name="Taro \"T\" Yamada" reason="" file=a.vcf"#,
+            format_record("SKIP", &[
+                ("card", 2.to_string()),
+                ("line", 10.to_string()),
+                ("name", "Taro \"T\" Yamada".to_string()),
+                ("reason", String::new()),
+                ("file", "a.vcf".to_string()),
+            ])
+        );
+    }
+}
@@ -1,297 +1,592 @@
-mod vcf_parser;
-mod ini_io;
-mod error_flg;
-mod arg_and_help;
-mod file_fns;
-mod xml_parser;
-mod progress_bar;
-
 use std::process::exit;
-use vcf_parser::*;
-use ini_io::*;
-use error_flg::*;
-use arg_and_help::*;
-use file_fns::*;
-use xml_parser::*;
-use progress_bar::*;
+use vcf2msip::vcf_parser::*;
+use vcf2msip::ini_io::*;
+use vcf2msip::call_log::*;
+use vcf2msip::error_flg::*;
+use vcf2msip::arg_and_help::*;
+use vcf2msip::file_fns::*;
+use vcf2msip::xml_parser::*;
+use vcf2msip::progress_bar::*;
+use vcf2msip::sort_order::*;
+use vcf2msip::ini_command::*;
+use vcf2msip::vcf_check::*;
+use vcf2msip::conv_report::*;
+use vcf2msip::logger::*;
+use vcf2msip::backups::*;
+use vcf2msip::in_use::*;
+use vcf2msip::msip_dir::*;
+use vcf2msip::phonebook_source::*;
+use vcf2msip::watch::*;
+use vcf2msip::review::*;
 
 use encoding_rs::SHIFT_JIS;
-use std::io::{BufWriter, Write};
+use std::collections::HashMap;
+use std::fs::create_dir_all;
+use std::io::{stderr, stdin, stdout, BufWriter, Write};
+use std::time::Instant;
 
-#[derive(Default)]
-struct ProcCounter {
-    all_contact: usize,
-    all_telephone: usize,
-    contact: usize,
-    telephone: usize,
-    merge: usize,
-    logs: usize,
+fn main() {
+    // cargo run -- -m -r -n .\sandbox\contacts.vcf .\sandbox\Contacts.xml
+
+    let args = Args::get_params();
+    set_log_level(args.log_level());
+    log_verbose(&format!("{:?}", args));
+
+    let res = if args.is_find_microsip() {
+        run_find_microsip()
+    } else if args.is_ini_command() {
+        run_ini_command(&args)
+    } else if args.is_check_command() {
+        run_check(&args)
+    } else if args.is_backups_command() {
+        run_backups_command(&args)
+    } else if args.is_completions_command() {
+        args.write_completions(&mut stdout())
+    } else if args.is_man_command() {
+        args.write_man(&mut stdout())
+    } else if args.is_watch() {
+        run_watch(&args)
+    } else {
+        let mut report = ConvReport::default();
+        let res = conv(&args, &mut report);
+        if args.is_json() {
+            res.and(output_report(&args, &report, res.err()))
+        } else {
+            res
+        }
+    };
+    if let Err(e) = res {
+        print_err_msg(e);
+        exit(e);
+    };
 }
 
-impl ProcCounter {
-    pub fn add_count(&mut self, pc: &ProcCounter) {
-        self.all_contact += pc.all_contact;
-        self.all_telephone += pc.all_telephone;
-        self.contact += pc.contact;
-        self.telephone += pc.telephone;
-        self.merge += pc.merge;
-        self.logs += pc.logs;
-    }
-    pub fn print(&self) {
-        println!(
-            "ALL VCF CONTACTS: {} / ALL VCF TELEPHONES: {}",
-            self.all_contact, self.all_telephone
-        );
-        println!(
-            "PROCESSED [Contact:{}, Telephone:{}, Merge:{}, RenewLogs:{}]",
-            self.contact,
-            self.telephone,
-            self.merge,
-            self.logs
-        );
+/// Write json report to file or stdout
+fn output_report(args: &Args, report: &ConvReport, error: Option<i32>) -> Result<(), i32> {
+    let json = report.to_json(error);
+    if args.report_file_name().is_empty() {
+        println!("{}", json);
+        return Ok(());
     }
+    let mut hfile = match File::create(args.report_file_name()) {
+        Ok(h) => h,
+        Err(_) => { return Err(_ERR_CREATE_FILE); }
+    };
+    if writeln!(hfile, "{}", json).is_err() {
+        return Err(_ERR_WRITE_FILE);
+    }
+    Ok(())
 }
 
-fn main() {
-    // cargo run -- -m -r -n .\sandbox\contacts.vcf .\sandbox\Contacts.xml
+/// Convert at start, and again every time watched files are changed
+///
+/// Error of one cycle is printed, and watching goes on. Failed cycle is retried,
+/// like while MicroSIP is running, so the change is not lost.
+fn run_watch(args: &Args) -> Result<(), i32> {
+    let mut filenames = vec![args.load_file_name()];
+    if args.is_watch_xml() {
+        filenames.push(args.save_file_name());
+    }
+    let mut watcher = Watcher::new(&filenames, args.watch_debounce());
+    let mut changed: Vec<String> = Vec::new();
+    let mut last_error: Option<i32> = None;
+    for cycle in 1.. {
+        if cycle == 1 {
+            log_info(&format!("WATCH cycle {}: start", cycle));
+        } else if changed.is_empty() {
+            log_info(&format!("WATCH cycle {}: retry", cycle));
+        } else {
+            log_info(&format!("WATCH cycle {}: changed {}", cycle, changed.join(", ")));
+        }
+        let mut report = ConvReport::default();
+        let res = conv(args, &mut report);
+        if args.is_json() {
+            output_report(args, &report, res.err())?;
+        }
+        let pc = &report.pc;
+        match res {
+            Ok(()) => log_info(&format!(
+                "WATCH cycle {}: done [Contact:{}, Telephone:{}, Merge:{}, RenewLogs:{}, SpeedDial:{}]",
+                cycle, pc.contact, pc.telephone, pc.merge, pc.logs, pc.speed_dial
+            )),
+            Err(e) => {
+                // Same error of retries is printed once
+                if last_error != Some(e) {
+                    print_err_msg(e);
+                }
+                log_info(&format!("WATCH cycle {}: failed {}, retry after {} seconds", cycle, e, args.watch_debounce()));
+            },
+        }
+        last_error = res.err();
+        // Contacts.xml written by this cycle is not a change
+        watcher.reset(args.save_file_name());
+        if res.is_err() {
+            watcher.retry(std::mem::take(&mut changed), Instant::now());
+        }
+        log_info(&format!("Watching {} ... Ctrl+C to stop.", filenames.join(", ")));
+        changed = watcher.wait();
+    }
+    Ok(())
+}
 
-    let args = Args::get_params();
-    if args.is_help() {
-        args.print_help();
-        return ();
+/// Print warning message, and keep it for report
+fn warn(report: &mut ConvReport, msg: String) {
+    log_warn(&msg);
+    report.warnings.push(msg);
+}
+
+/// Keep skipped contact for report
+fn skip_contact(report: &mut ConvReport, result: ContactResult) {
+    log_record("SKIP", &[
+        ("card", result.card.to_string()),
+        ("line", result.line.to_string()),
+        ("name", result.name.clone()),
+        ("reason", result.skip_reason.unwrap_or_default().to_string()),
+    ]);
+    report.contacts.push(result);
+}
+
+/// Keep created backup file for report
+fn add_backup(report: &mut ConvReport, bup_filename: String) {
+    log_record("BACKUP", &[("file", bup_filename.clone())]);
+    report.backups.push(bup_filename);
+}
+
+/// Fetch vcards from CardDAV server to vcf file
+#[cfg(feature = "carddav")]
+fn pull_carddav(args: &Args) -> Result<(), i32> {
+    use vcf2msip::carddav::*;
+    let client = CardDav::new(args.carddav_url(), Auth::from_env(args.carddav_user()));
+    let result = pull_to_vcf(&client, args.load_file_name())?;
+    if result.is_full {
+        log_info(&format!("CARDDAV full sync: {} cards", result.total));
+    } else {
+        log_info(&format!(
+            "CARDDAV sync: {} changed, {} deleted, {} cards",
+            result.changed, result.deleted, result.total
+        ));
     }
+    Ok(())
+}
 
-    if let Err(e) = conv(&args) {
-        print_err_msg(e);
-        exit(e);
+#[cfg(not(feature = "carddav"))]
+fn pull_carddav(_args: &Args) -> Result<(), i32> {
+    Err(_ERR_FEATURE_DISABLED)
+}
+
+/// Search LDAP directory to vcf file
+#[cfg(feature = "ldap")]
+fn pull_ldap(args: &Args) -> Result<(), i32> {
+    use vcf2msip::ldap_source::*;
+    let query = LdapQuery {
+        url: args.ldap_url().to_string(),
+        base_dn: args.ldap_base().to_string(),
+        filter: args.ldap_filter().to_string(),
+        bind_dn: args.ldap_bind().to_string(),
+        password: std::env::var(ENV_LDAP_PASSWORD).unwrap_or_default(),
+        mapping: args.ldap_mapping().clone(),
     };
+    let count = search_to_vcf(&query, args.load_file_name())?;
+    log_info(&format!("LDAP: {} entries", count));
+    Ok(())
+}
+
+#[cfg(not(feature = "ldap"))]
+fn pull_ldap(_args: &Args) -> Result<(), i32> {
+    Err(_ERR_FEATURE_DISABLED)
 }
 
 /// Process of convert
-fn conv(args: &Args) -> Result<(), i32> {
+fn conv(args: &Args, report: &mut ConvReport) -> Result<(), i32> {
 
-    if !is_exists_io_files(&args) {
+    if args.save_file_name().is_empty() {
+        return Err(_ERR_MICROSIP_NOT_FOUND);
+    }
+    if !args.carddav_url().is_empty() {
+        pull_carddav(args)?;
+    }
+    if !args.ldap_url().is_empty() {
+        pull_ldap(args)?;
+    }
+    if !is_exists_io_files(args) {
         return Err(_ERR_FILE_NOT_FOUND);
     }
 
+    // Convert CSV or XML phonebook to temporary vcf file
+    let input_vcf = TempFile::new("input.vcf");
+    let load_file_name = if args.input_format() != INPUT_FORMAT_VCF {
+        let (count, format) = input_to_vcf(args.input_format(), args.load_file_name(), args.csv_options(), input_vcf.filename())?;
+        log_verbose(&format!("{}: {} contacts", format, count));
+        input_vcf.filename()
+    } else {
+        args.load_file_name()
+    };
+
+    // Review and edit contacts, to temporary vcf file
+    let review_vcf = TempFile::new("review.vcf");
+    let load_file_name = if args.is_interactive() {
+        let sip_contacts = if args.is_merge() {
+            SipContacts::new(args.save_file_name())?
+        } else {
+            SipContacts::empty()
+        };
+        let mut review = Review::new(&Vcf::new(load_file_name)?, args.name_pattern_normal(), args.dial_format())?;
+        if !review.run(stdin().lock(), &mut stderr(), &sip_contacts)? {
+            log_info("Nothing is written.");
+            return Ok(());
+        }
+        let (renamed, off) = review.edit_counts();
+        log_info(&format!("REVIEW: {} renamed, {} numbers off", renamed, off));
+        review.write_vcf(review_vcf.filename())?;
+        review_vcf.filename()
+    } else {
+        load_file_name
+    };
+
     // Read vcf file
-    let vcf = match Vcf::new(&args.load_file_name()) {
+    let vcf = match Vcf::new(load_file_name) {
         Ok(vcf) => vcf,
         Err(e) => { return Err(e); },
     };
+    // Count once for progress bars, vcf is read again by each step
+    let card_count = vcf.count()?;
 
     // Output AGEphone's csv file only
     if args.is_output_csv_agephone() {
         // cargo run -- -ca -n .\sandbox\contacts.vcf .\sandbox\Contacts.csv
 
-        let mut hfile = match File::create(&args.save_file_name()) {
-            Ok(h) => h,
-            Err(_) => { return Err(_ERR_CREATE_FILE); }
-        };
-        let mut pc = ProcCounter::default();
-        match output_age_phone_csv_file(&vcf, &args, &mut hfile) {
-            Ok(res_pc) => {
-                pc.add_count(&res_pc);
-            },
-            Err(e) => {
-                delete_file(&args.save_file_name());
-                return Err(e);
-            },
-        };
-        pc.print();
+        // Write to temporary file, csv is not replaced if failed
+        let mut tx = FileTransaction::new(None);
+        let csv_tmp_filename = tx.add(args.save_file_name());
+        {
+            let mut hfile = match File::create(&csv_tmp_filename) {
+                Ok(h) => h,
+                Err(_) => { return Err(_ERR_CREATE_FILE); }
+            };
+            output_age_phone_csv_file(&vcf, card_count, args, &mut hfile, report)?;
+        }
+        tx.commit()?;
+        report.pc.print();
         return Ok(())
     }
 
+    // Prepare all files to temporary files, nothing is replaced if failed
+    let bup_filenames = [args.save_file_name(), args.microsip_ini_file()];
+    wait_files_free(&bup_filenames, args.wait_secs(), args.is_force())?;
+    let bup_number = (!args.is_no_bup()).then(|| new_bup_number(&bup_filenames));
+    let mut tx = FileTransaction::new(bup_number);
+    let xml_tmp_filename = tx.add(args.save_file_name());
     // Read MicroSIP Contacts.xml file
     let mut sip_contacts = if args.is_merge() {
-        match SipContacts::new(&args.save_file_name()) {
+        match SipContacts::new(args.save_file_name()) {
             Ok(sc) => sc,
             Err(e) => { return Err(e); },
         }
     } else {
         SipContacts::empty()
     };
-
     // Output new xml to temporary file
-    let tmp_filename = make_tmp_filename(&args.save_file_name());
-    let mut hfile = match File::create(&tmp_filename) {
-        Ok(h) => h,
-        Err(_) => { return Err(_ERR_CREATE_FILE); }
-    };
-    let mut pc = ProcCounter::default();
-    match output_xml_file(&vcf, &args,&mut hfile, &mut sip_contacts) {
-        Ok(res_pc) => {
-            pc.add_count(&res_pc);
-        },
-        Err(e) => {
-            delete_file(&tmp_filename);
-            return Err(e);
-        },
-    };
-    // Backup original xml file
-    if !args.is_no_bup() {
-        if let Err(e) = file_backup(&args.save_file_name()) {
-            delete_file(&tmp_filename);
-            return Err(e);
+    {
+        let mut hfile = match File::create(&xml_tmp_filename) {
+            Ok(h) => h,
+            Err(_) => { return Err(_ERR_CREATE_FILE); }
+        };
+        output_xml_file(&vcf, card_count, args, &mut hfile, &mut sip_contacts, report)?;
+        if hfile.flush().is_err() {
+            return Err(_ERR_WRITE_FILE);
         }
     }
-    // Apply temporary file to true Contact.xml file
-    if let Err(_) = rename(&tmp_filename, &args.save_file_name()) {
-        delete_file(&tmp_filename);
-        return Err(_ERR_FIX_FILE_COPY);
-    }
-
-    // Renew logs name in MicroSIP.ini
-    if args.is_renew_logs() {
+    // Renew logs name and speed dial in MicroSIP.ini
+    if args.is_renew_logs() || args.is_speed_dial() {
         // Read ini file to buffer
-        let mut ini_io = match IniIo::new(&args.microsip_ini_file()) {
+        let ini_tmp_filename = tx.add(args.microsip_ini_file());
+        let mut ini_io = match IniIo::new(args.microsip_ini_file()) {
             Ok(iniio) => iniio,
             Err(_) => { return Err(_ERR_DID_NOT_RUN_RENEW_LOGS) },
         };
         // Output renewed buffer to temporary file
-        pc.add_count(&renew_ini_buffer(&vcf, &args, &mut ini_io));
-        let tmp_filename = make_tmp_filename(&args.microsip_ini_file());
-        if let Err(e) = ini_io.save(&tmp_filename) {
-            delete_file(&tmp_filename);
-            return Err(e);
+        if args.is_renew_logs() {
+            renew_ini_buffer(&vcf, card_count, args, &mut ini_io, report)?;
         }
-        // Backup original ini file
-        if !args.is_no_bup() {
-            if let Err(e) = file_backup(&args.microsip_ini_file()) {
-                delete_file(&tmp_filename);
-                return Err(e);
-            }
+        if args.is_speed_dial() {
+            renew_ini_shortcuts(&vcf, args, &mut ini_io, report)?;
         }
-        // Apply temporary file to true MicroSIP.ini file
-        match rename(&tmp_filename, &args.microsip_ini_file()) {
-            Ok(()) => (),
-            Err(_) => { return Err(_ERR_FIX_FILE_COPY); },
+        ini_io.save(&ini_tmp_filename)?;
+    }
+    // Backup originals, and replace Contacts.xml and MicroSIP.ini together
+    for bup_filename in tx.commit()? {
+        add_backup(report, bup_filename);
+    }
+    tx.verify()?;
+    // Delete old backups
+    if args.keep_backups() > 0 {
+        for bup_filename in prune_backups(&bup_filenames, args.keep_backups()) {
+            log_record("PRUNE", &[("file", bup_filename.clone())]);
+            report.pruned_backups.push(bup_filename);
         }
     }
-    pc.print();
+    report.pc.print();
     Ok(())
 }
 
 /// Write to xml file
 fn output_xml_file(
-    vcf: &Vcf, args: &Args, hfile: &mut File, sip_contacts: &mut SipContacts
-) -> Result<ProcCounter, i32> {
+    vcf: &Vcf, card_count: usize, args: &Args, hfile: &mut File, sip_contacts: &mut SipContacts, report: &mut ConvReport
+) -> Result<(), i32> {
     // Write start tag
-    if let Err(_) = writeln!(hfile, "<?xml version=\"1.0\"?>\r\n<contacts>\r") {
+    if writeln!(hfile, "<?xml version=\"1.0\"?>\r\n<contacts>\r").is_err() {
         return Err(_ERR_WRITE_FILE);
     }
     // Loop at vcards
     let mut pc = ProcCounter::default();
-    let vcf_vcards = vcf.get_vcards();
-    pc.all_contact = vcf_vcards.len();
+    let mut rows: SortRows<(String, bool)> = SortRows::new(args.sort_order());
+    let mut written: HashMap<String, String> = HashMap::new(); // number(numeric only) => name
+    pc.all_contact = card_count;
     let mut pgbar = ProgressBar::new("Convert", pc.all_contact);
-    for vcard in vcf_vcards {
+    let mut reader = vcf.reader()?;
+    let mut card = 0;
+    while let Some(vcard) = reader.next() {
         pgbar.progress();
+        card += 1;
         // Parse one contact, Loop at telephone
-        let ct = Contact::new(&vcard);
-        if ct.is_empty() { continue; }
+        let ct = Contact::new(&vcard?);
+        let mut result = ContactResult {
+            card, line: reader.card_line_no(), name: ct.full_name().to_string(),
+            skip_reason: None, numbers: Vec::new(), photo: None,
+        };
+        if ct.is_empty() {
+            result.skip_reason = Some(SKIP_NO_NUMBER);
+            skip_contact(report, result);
+            continue;
+        }
+        result.photo = save_photo(&ct, args, card, report);
         let finitial = ct.finitial();
         let linitial = ct.linitial();
+        let is_blf = ct.speed_dial_order(args.speed_dial_category()).is_some();
         for tel in ct.tel_iter() {
             pc.all_telephone += 1;
-            let number = tel.number();
-            // Clear original contact for merge
+            let dial = tel.dial(args.dial_format());
+            let number = dial.as_str();
+            let new_name = ct.fmt_name(
+                args.name_pattern_normal(), &finitial, &linitial, tel.teltype()
+            );
+            // Clear original contact for merge, report it if named differently
             if !sip_contacts.is_empty() {
-                sip_contacts.clear_exist(number);
+                for (old_number, old_name) in sip_contacts.clear_exist(number) {
+                    if old_name == new_name { continue; }
+                    report.collisions.push(Collision {
+                        number: old_number, name: new_name.clone(), other_name: old_name, source: "xml",
+                    });
+                }
             };
-            // Write one element
-            let new_name = ct.fmt_name(
-                &args.name_pattern_normal(), &finitial, &linitial, tel.teltype()
-            ).replace("\"", "&quot;");
-            if let Err(_) = writeln!(hfile, "{}\r", Contact::xml_line(&new_name, number)) {
-                continue;
+            // Same number in other vcf contact
+            let fixed = number_domain_key(number);
+            if !fixed.is_empty() {
+                if let Some(other_name) = written.insert(fixed, new_name.clone()) {
+                    report.collisions.push(Collision {
+                        number: number.to_string(), name: new_name.clone(), other_name, source: "vcf",
+                    });
+                }
+            }
+            // Stock one element
+            let key = SortKey::from_contact(args.sort_order(), &ct, &new_name, tel.number());
+            let line = Contact::xml_line(&new_name, number, is_blf);
+            if let Some(row) = rows.push(key, (line, false)) {
+                write_xml_row(hfile, row, &mut pc)?;
             }
-            pc.telephone += 1;
+            result.numbers.push((number.to_string(), new_name));
         }
+        report.contacts.push(result);
         pc.contact += 1;
     }
+    report_malformed(report, reader.malformed());
     // Merge remaining original contact
     if !sip_contacts.is_empty() {
         let mut pgbar = ProgressBar::new("Merge", sip_contacts.data().len());
         for (sct_fix_number, number, name) in sip_contacts.data() {
             pgbar.progress();
             if sct_fix_number.is_empty() { continue; }
-            // Write one element
-            if let Err(_) = writeln!(hfile, "{}\r", Contact::xml_line(&name, &number)) {
-                continue;
+            // Stock one element
+            let key = SortKey::from_name(args.sort_order(), name, number);
+            if let Some(row) = rows.push(key, (Contact::xml_line(name, number, false), true)) {
+                write_xml_row(hfile, row, &mut pc)?;
             }
-            pc.merge += 1;
+            report.merged.push((number.to_string(), name.to_string()));
         }
     }
+    // Write sorted elements
+    for row in rows.into_sorted() {
+        write_xml_row(hfile, row, &mut pc)?;
+    }
     // Write end tag
-    if let Err(_) = writeln!(hfile, "</contacts>\r") {
+    if writeln!(hfile, "</contacts>\r").is_err() {
         return Err(_ERR_WRITE_FILE);
     }
-    Ok(pc)
+    report.pc.add_count(&pc);
+    Ok(())
+}
+
+/// Write embedded photo to photo directory, named by card index and first number
+///
+/// Return written file or URI of photo, for report. Failed writing is a warning.
+fn save_photo(ct: &Contact, args: &Args, card: usize, report: &mut ConvReport) -> Option<String> {
+    if args.photo_dir().is_empty() {
+        return None;
+    }
+    let (ext, data) = match ct.photo()? {
+        Photo::Uri(uri) => return Some(uri),
+        Photo::Image { ext, data } => (ext, data),
+    };
+    let id = ct.tel_iter()
+        .filter(|t| !is_sip_uri(t.number()))
+        .map(|t| fix_number(split_ext(t.number()).0))
+        .find(|n| !n.is_empty())
+        .map_or_else(|| card.to_string(), |n| format!("{}_{}", card, n));
+    let filename = Path::new(args.photo_dir()).join(format!("{}.{}", id, ext)).to_string_lossy().to_string();
+    if create_dir_all(args.photo_dir()).and_then(|_| std::fs::write(&filename, data)).is_err() {
+        warn(report, format!("{}: {}", filename, err_msg(_ERR_WRITE_FILE)));
+        return None;
+    }
+    log_record("PHOTO", &[("card", card.to_string()), ("file", filename.clone())]);
+    Some(filename)
+}
+
+/// Report skipped text in vcf
+fn report_malformed(report: &mut ConvReport, malformed: &[MalformedCard]) {
+    for m in malformed {
+        warn(report, format!("vcf line {} (offset {}): {}", m.line_no, m.offset, m.reason));
+    }
+}
+
+/// Write one xml element
+fn write_xml_row(hfile: &mut File, (line, is_merge): (String, bool), pc: &mut ProcCounter) -> Result<(), i32> {
+    if writeln!(hfile, "{}\r", line).is_err() {
+        return Err(_ERR_WRITE_FILE);
+    }
+    if is_merge { pc.merge += 1; } else { pc.telephone += 1; }
+    Ok(())
 }
 
 /// Replace MicroSIP.ini on buffer
-fn renew_ini_buffer(vcf: &Vcf, args: &Args, ini_io: &mut IniIo) -> ProcCounter {
-    let mut pc = ProcCounter::default();
-    let vcf_vcards = vcf.get_vcards();
-    let mut pgbar = ProgressBar::new("ReNew Logs", vcf_vcards.len());
-    for vcard in vcf_vcards {
+fn renew_ini_buffer(
+    vcf: &Vcf, card_count: usize, args: &Args, ini_io: &mut IniIo, report: &mut ConvReport
+) -> Result<(), i32> {
+    let mut pgbar = ProgressBar::new("ReNew Logs", card_count);
+    for vcard in vcf.reader()? {
         // Parse one contact, Loop at telephone
         pgbar.progress();
-        let ct = Contact::new(&vcard);
+        let ct = Contact::new(&vcard?);
         if ct.is_empty() { continue; }
         let finitial = ct.finitial();
         let linitial = ct.linitial();
         for tel in ct.tel_iter() {
             // Replace buffer
-            for old_line in ini_io.get_match_number_lines(tel.number()) {
-                let new_name = ct.fmt_name(
-                    &args.name_pattern_normal(), &finitial, &linitial, tel.teltype()
-                ).replace(";", "|");
-                let new_line = IniIo::make_new_number_line(&old_line, &new_name);
-                if !new_line.is_empty() {
-                    ini_io.replace(&old_line, &new_line);
-                    pc.logs += 1;
-                }
+            let new_name = ct.fmt_name(
+                args.name_pattern_normal(), &finitial, &linitial, tel.teltype()
+            ).replace(";", "|");
+            for ce in ini_io.rename_calls(tel.number(), &new_name) {
+                report.renamed_logs.push(RenamedLog {
+                    id: ce.id().to_string(),
+                    number: ce.number().to_string(),
+                    old_name: ce.name().to_string(),
+                    new_name: new_name.clone(),
+                });
+                report.pc.logs += 1;
             }
         }
     }
-    pc
+    // Report entries could not be renamed
+    for line in ini_io.unparsed_calls() {
+        warn(report, format!("Unknown call log entry, not renamed: {}", line));
+    }
+    Ok(())
+}
+
+/// Replace speed dial buttons in MicroSIP.ini on buffer
+///
+/// Marked contacts are ordered by X-MICROSIP-SPEEDDIAL number, then vcf order.
+/// No marked contact keeps current buttons, a typo of category does not clear them.
+fn renew_ini_shortcuts(
+    vcf: &Vcf, args: &Args, ini_io: &mut IniIo, report: &mut ConvReport
+) -> Result<(), i32> {
+    let mut buttons: Vec<(usize, String, String)> = Vec::new(); // order, label, number
+    for vcard in vcf.reader()? {
+        let ct = Contact::new(&vcard?);
+        let Some(order) = ct.speed_dial_order(args.speed_dial_category()) else { continue; };
+        let finitial = ct.finitial();
+        let linitial = ct.linitial();
+        for tel in ct.tel_iter() {
+            let label = ct.fmt_name(
+                args.name_pattern_normal(), &finitial, &linitial, tel.teltype()
+            ).replace(";", "|");
+            buttons.push((order, label, tel.dial(args.dial_format())));
+        }
+    }
+    if buttons.is_empty() {
+        warn(report, format!(
+            "No speed dial contacts of category \"{}\", [{}] is not changed", args.speed_dial_category(), INI_SECTION_SHORTCUTS
+        ));
+        return Ok(());
+    }
+    buttons.sort_by_key(|b| b.0);
+    // Renew all buttons: "<index>=<label>;<number>;<type:0=call>"
+    ini_io.clear_section(INI_SECTION_SHORTCUTS);
+    for (idx, (_, label, number)) in buttons.iter().enumerate() {
+        ini_io.set(INI_SECTION_SHORTCUTS, &idx.to_string(), &format!("{};{};0", label, number));
+        report.pc.speed_dial += 1;
+    }
+    ini_io.set(INI_SECTION_SETTINGS, "shortcutsEnabled", "1");
+    Ok(())
 }
 
 /// Write to AGEphone's csv file
 fn output_age_phone_csv_file(
-    vcf: &Vcf, args: &Args, hfile: &mut File
-) -> Result<ProcCounter, i32> {
+    vcf: &Vcf, card_count: usize, args: &Args, hfile: &mut File, report: &mut ConvReport
+) -> Result<(), i32> {
     let mut bfw = BufWriter::new(hfile);
     // Loop on vcards
     let mut pc = ProcCounter::default();
-    let vcf_vcards = vcf.get_vcards();
-    pc.all_contact = vcf_vcards.len();
+    let mut rows: SortRows<String> = SortRows::new(args.sort_order());
+    pc.all_contact = card_count;
     let mut pgbar = ProgressBar::new("AGEphoneCSV", pc.all_contact);
-    for vcard in vcf_vcards {
+    let mut reader = vcf.reader()?;
+    let mut card = 0;
+    while let Some(vcard) = reader.next() {
         pgbar.progress();
+        card += 1;
         // Parse one contact, Loop at telephone
-        let ct = Contact::new(&vcard);
-        if ct.is_empty() { continue; }
+        let ct = Contact::new(&vcard?);
+        let mut result = ContactResult {
+            card, line: reader.card_line_no(), name: ct.full_name().to_string(),
+            skip_reason: None, numbers: Vec::new(), photo: None,
+        };
+        if ct.is_empty() {
+            result.skip_reason = Some(SKIP_NO_NUMBER);
+            skip_contact(report, result);
+            continue;
+        }
+        result.photo = save_photo(&ct, args, card, report);
         let finitial = ct.finitial(); // かなFirst頭文字
         let linitial = ct.linitial(); // かなLast頭文字
         let hira_name = ct.last_hira_fullname() + " " + &ct.first_hira_fullname(); // ふりがな
         for tel in ct.tel_iter() {
             pc.all_telephone += 1;
-            let number = tel.number();
+            let dial = tel.dial(args.dial_format());
+            let number = dial.as_str();
             let new_name = ct.fmt_name(
-                &args.name_pattern_normal(), &finitial, &linitial, tel.teltype()
-            ).replace("\"", "&quot;");
-            // SJISで1行書き出す
+                args.name_pattern_normal(), &finitial, &linitial, tel.teltype()
+            );
+            let key = SortKey::from_contact(args.sort_order(), &ct, &new_name, tel.number());
+            // 1行分を溜める
             let line = format!(
                 "{},{},{},{},{}\r\n",
-                new_name,
+                new_name.replace("\"", "&quot;"),
                 number,
                 ct.first_categories(),
                 hira_name,
                 match tel.teltype() {
                     // "" => "1", // IP電話
+                    SIP_TEL_TYPE => "1", // IP電話
                     "HOME" => "2", // 一般電話
                     "CELL" => "3", // 携帯電話
                     "携帯" => "3", // 携帯電話
@@ -299,14 +594,60 @@ fn output_age_phone_csv_file(
                     _ => "2"
                 }
             );
-            let (sjis_line, _, _) = SHIFT_JIS.encode(&line);
-            if let Err(_) = bfw.write(&sjis_line) {
-                continue;
+            if let Some(line) = rows.push(key, line) {
+                write_sjis_line(&mut bfw, &line, &mut pc)?;
             }
-
-            pc.telephone += 1;
+            result.numbers.push((number.to_string(), new_name));
         }
+        report.contacts.push(result);
         pc.contact += 1;
     }
-    Ok(pc)
+    report_malformed(report, reader.malformed());
+    for line in rows.into_sorted() {
+        write_sjis_line(&mut bfw, &line, &mut pc)?;
+    }
+    if bfw.flush().is_err() {
+        return Err(_ERR_WRITE_FILE);
+    }
+    report.pc.add_count(&pc);
+    Ok(())
+}
+
+/// SJISで1行書き出す
+fn write_sjis_line(bfw: &mut BufWriter<&mut File>, line: &str, pc: &mut ProcCounter) -> Result<(), i32> {
+    let (sjis_line, _, _) = SHIFT_JIS.encode(line);
+    if bfw.write_all(&sjis_line).is_err() {
+        return Err(_ERR_WRITE_FILE);
+    }
+    pc.telephone += 1;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_renew_ini_shortcuts() {
+        let vcf = Vcf::new("testfiles/test.vcf").unwrap();
+        let ini_text = "[Settings]\r\nshortcutsEnabled=0\r\n[Shortcuts]\r\n0=Old;100;0\r\n1=Old2;101;0\r\n";
+        // Contact of category is written, old buttons are removed
+        let args = Args::from_args(["vcf2msip", "in.vcf", "out.xml", "-sd", "--speed-dial-category", "Business"]).unwrap();
+        let mut ini_io = IniIo::from_text(ini_text);
+        let mut report = ConvReport::default();
+        renew_ini_shortcuts(&vcf, &args, &mut ini_io, &mut report).unwrap();
+        assert_eq!(Some("C - CORPCORP (WORK);987654;0"), ini_io.get(INI_SECTION_SHORTCUTS, "0"));
+        assert_eq!(None, ini_io.get(INI_SECTION_SHORTCUTS, "1"));
+        assert_eq!(Some("1"), ini_io.get(INI_SECTION_SETTINGS, "shortcutsEnabled"));
+        assert_eq!(1, report.pc.speed_dial);
+        // No marked contact keeps buttons
+        let args = Args::from_args(["vcf2msip", "in.vcf", "out.xml", "-sd", "--speed-dial-category", "Typo"]).unwrap();
+        let mut ini_io = IniIo::from_text(ini_text);
+        let mut report = ConvReport::default();
+        renew_ini_shortcuts(&vcf, &args, &mut ini_io, &mut report).unwrap();
+        assert_eq!(Some("Old;100;0"), ini_io.get(INI_SECTION_SHORTCUTS, "0"));
+        assert_eq!(Some("Old2;101;0"), ini_io.get(INI_SECTION_SHORTCUTS, "1"));
+        assert_eq!(Some("0"), ini_io.get(INI_SECTION_SETTINGS, "shortcutsEnabled"));
+        assert_eq!(1, report.warnings.len());
+    }
 }
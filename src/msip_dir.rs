@@ -0,0 +1,162 @@
+use crate::arg_and_help;
+use crate::error_flg;
+
+use std::env;
+use std::fs::read_dir;
+use std::path::{Path, PathBuf};
+use arg_and_help::*;
+use error_flg::*;
+
+pub const ENV_MICROSIP_DIR: &str = "VCF2MSIP_MICROSIP_DIR";
+const MICROSIP_DIR_NAME: &str = "MicroSIP";
+
+/// Candidate of MicroSIP data directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MicrosipDir {
+    source: &'static str,
+    dir: PathBuf,
+}
+
+impl MicrosipDir {
+
+    pub fn new(source: &'static str, dir: PathBuf) -> Self {
+        Self { source, dir }
+    }
+
+    pub fn source(&self) -> &str { self.source }
+    pub fn dir(&self) -> String { self.dir.to_string_lossy().to_string() }
+    pub fn contacts_xml(&self) -> String { self.dir.join(CONTACTS_XML_FILE).to_string_lossy().to_string() }
+    pub fn microsip_ini(&self) -> String { self.dir.join(INI_DEFAULT_FILE).to_string_lossy().to_string() }
+    pub fn has_contacts_xml(&self) -> bool { self.dir.join(CONTACTS_XML_FILE).is_file() }
+    pub fn has_microsip_ini(&self) -> bool { self.dir.join(INI_DEFAULT_FILE).is_file() }
+
+    /// MicroSIP has written its files here
+    pub fn is_found(&self) -> bool {
+        self.has_contacts_xml() || self.has_microsip_ini()
+    }
+
+    /// Return one line for "--find-microsip"
+    pub fn to_line(&self, is_used: bool) -> String {
+        let files: Vec<&str> = [
+            (self.has_contacts_xml(), CONTACTS_XML_FILE),
+            (self.has_microsip_ini(), INI_DEFAULT_FILE),
+        ].iter().filter(|(has, _)| *has).map(|(_, name)| *name).collect();
+        format!(
+            "{} [{}] {} ({})",
+            if is_used { "*" } else { " " },
+            self.source,
+            self.dir(),
+            if files.is_empty() { "not found".to_string() } else { files.join(", ") }
+        )
+    }
+}
+
+/// Return candidates of MicroSIP data directory, in priority order
+pub fn find_microsip_dirs() -> Vec<MicrosipDir> {
+    let exe_dir = env::current_exe().ok().and_then(|p| p.parent().map(|d| d.to_path_buf()));
+    candidate_dirs(|key| env::var(key).ok(), exe_dir)
+}
+
+/// Return first MicroSIP data directory having its files
+pub fn find_microsip_dir() -> Option<MicrosipDir> {
+    find_microsip_dirs().into_iter().find(|d| d.is_found())
+}
+
+/// Process of "--find-microsip", print all candidates and mark used one
+pub fn run_find_microsip() -> Result<(), i32> {
+    let dirs = find_microsip_dirs();
+    let used = dirs.iter().position(|d| d.is_found());
+    for (i, dir) in dirs.iter().enumerate() {
+        println!("{}", dir.to_line(used == Some(i)));
+    }
+    match used {
+        Some(_) => Ok(()),
+        None => Err(_ERR_MICROSIP_NOT_FOUND),
+    }
+}
+
+/// Return candidates from environment variables and executable directory
+///
+/// 1. Environment variable `VCF2MSIP_MICROSIP_DIR`
+/// 2. Portable install, vcf2msip is put beside microsip.exe
+/// 3. `%APPDATA%\MicroSIP`
+/// 4. Wine prefix `$WINEPREFIX` or `~/.wine`, of every user
+fn candidate_dirs(env_var: impl Fn(&str) -> Option<String>, exe_dir: Option<PathBuf>) -> Vec<MicrosipDir> {
+    let mut dirs: Vec<MicrosipDir> = Vec::new();
+    let env_path = |key: &str| env_var(key).filter(|s| !s.is_empty()).map(PathBuf::from);
+    if let Some(dir) = env_path(ENV_MICROSIP_DIR) {
+        dirs.push(MicrosipDir::new("env", dir));
+    }
+    if let Some(dir) = exe_dir {
+        dirs.push(MicrosipDir::new("portable", dir));
+    }
+    if let Some(appdata) = env_path("APPDATA") {
+        dirs.push(MicrosipDir::new("appdata", appdata.join(MICROSIP_DIR_NAME)));
+    }
+    let wine_prefix = env_path("WINEPREFIX").or_else(|| env_path("HOME").map(|h| h.join(".wine")));
+    if let Some(drive_c) = wine_prefix.map(|p| p.join("drive_c")) {
+        for user_dir in sub_dirs(&drive_c.join("users")) {
+            dirs.push(MicrosipDir::new("wine", user_dir.join("AppData").join("Roaming").join(MICROSIP_DIR_NAME)));
+            dirs.push(MicrosipDir::new("wine", user_dir.join("Application Data").join(MICROSIP_DIR_NAME)));
+        }
+        for program_files in ["Program Files", "Program Files (x86)"] {
+            dirs.push(MicrosipDir::new("wine-portable", drive_c.join(program_files).join(MICROSIP_DIR_NAME)));
+        }
+    }
+    let mut seen: Vec<PathBuf> = Vec::new();
+    dirs.retain(|d| {
+        if seen.contains(&d.dir) { return false; }
+        seen.push(d.dir.clone());
+        true
+    });
+    dirs
+}
+
+/// Return sub directories, in name order
+fn sub_dirs(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = read_dir(dir) else { return Vec::new(); };
+    let mut dirs: Vec<PathBuf> = entries.flatten().map(|e| e.path()).filter(|p| p.is_dir()).collect();
+    dirs.sort();
+    dirs
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    #[test]
+    fn test_candidate_dirs() {
+        let root = env::temp_dir().join(format!("vcf2msip_test_msip_dir_{}", std::process::id()));
+        let _ = remove_dir_all(&root);
+        let roaming = root.join("wine/drive_c/users/taro/AppData/Roaming/MicroSIP");
+        create_dir_all(&roaming).unwrap();
+        create_dir_all(root.join("wine/drive_c/users/Public")).unwrap();
+        write(roaming.join("MicroSIP.ini"), "").unwrap();
+        let wine = root.join("wine").to_string_lossy().to_string();
+        let dirs = candidate_dirs(
+            |key| match key {
+                "WINEPREFIX" => Some(wine.clone()),
+                "APPDATA" => Some("".to_string()),
+                _ => None,
+            },
+            Some(root.join("bin")),
+        );
+        assert_eq!(
+            vec!["portable", "wine", "wine", "wine", "wine", "wine-portable", "wine-portable"],
+            dirs.iter().map(|d| d.source()).collect::<Vec<&str>>()
+        );
+        let found: Vec<&MicrosipDir> = dirs.iter().filter(|d| d.is_found()).collect();
+        assert_eq!(1, found.len());
+        assert_eq!(roaming, found[0].dir);
+        assert!(found[0].to_line(true).ends_with("MicroSIP (MicroSIP.ini)"));
+        // Environment variable is first
+        let dirs = candidate_dirs(
+            |key| (key == ENV_MICROSIP_DIR).then(|| "/opt/MicroSIP".to_string()),
+            Some(PathBuf::from("/opt/MicroSIP")),
+        );
+        assert_eq!(1, dirs.len());
+        assert_eq!("env", dirs[0].source());
+        remove_dir_all(&root).unwrap();
+    }
+}
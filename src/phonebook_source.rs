@@ -0,0 +1,196 @@
+use crate::csv_source;
+use crate::error_flg;
+use crate::vcf_parser;
+use crate::xml_parser;
+
+use std::fs::{read, write};
+use csv_source::*;
+use error_flg::*;
+use vcf_parser::*;
+use xml_parser::{xml_attr, xml_elements, xml_tag_text};
+
+pub const INPUT_FORMAT_AUTO: &str = "auto";
+pub const INPUT_FORMAT_VCF: &str = "vcf";
+pub const INPUT_FORMAT_CSV: &str = "csv";
+pub const INPUT_FORMAT_AGEPHONE: &str = "agephone";
+pub const INPUT_FORMAT_XML: &str = "xml";
+pub const INPUT_FORMATS: &[&str] = &[INPUT_FORMAT_AUTO, INPUT_FORMAT_VCF, INPUT_FORMAT_CSV, INPUT_FORMAT_AGEPHONE, INPUT_FORMAT_XML];
+
+/// Phone type digit of AGEphone CSV, reverse of output_age_phone_csv_file
+const AGEPHONE_TELTYPES: &[(&str, &str)] = &[
+    ("1", ""),     // IP電話
+    ("2", "HOME"), // 一般電話
+    ("3", "CELL"), // 携帯電話
+    ("4", "WORK"), // ビジネス
+];
+
+/// Attributes of Yealink remote phonebook "Unit" and local "contact", and telephone type
+const YEALINK_PHONE_ATTRS: &[(&str, &str)] = &[
+    ("Phone1", "WORK"), ("Phone2", "CELL"), ("Phone3", ""),
+    ("office_number", "WORK"), ("mobile_number", "CELL"), ("other_number", ""),
+];
+
+/// Check rows are AGEphone CSV, "name,number,category,reading,type"
+pub fn is_agephone_csv(rows: &[Vec<String>]) -> bool {
+    !rows.is_empty() && rows.iter().all(|row| {
+        row.len() == 5 && !row[1].trim().is_empty()
+            && AGEPHONE_TELTYPES.iter().any(|(digit, _)| *digit == row[4].trim())
+    })
+}
+
+/// Return contacts of AGEphone CSV, following rows of same name are one contact
+pub fn agephone_contacts(rows: &[Vec<String>]) -> Vec<ContactParts> {
+    let mut contacts: Vec<ContactParts> = Vec::new();
+    for row in rows.iter().filter(|r| r.len() >= 2) {
+        let cell = |i: usize| row.get(i).map_or("", |s| s.trim());
+        let teltype = AGEPHONE_TELTYPES.iter().find(|(digit, _)| *digit == cell(4)).map_or("", |(_, t)| t);
+        let mut parts = ContactParts::default();
+        parts.set("name", cell(0));
+        parts.set("categories", cell(2));
+        parts.set("phonetic-last", cell(3));
+        match contacts.last_mut() {
+            Some(last) if last.name == parts.name && last.categories == parts.categories
+                && last.phonetic_last == parts.phonetic_last => {
+                last.tels.push((teltype.to_string(), cell(1).to_string()));
+            },
+            _ => {
+                parts.tels.push((teltype.to_string(), cell(1).to_string()));
+                contacts.push(parts);
+            },
+        }
+    }
+    contacts
+}
+
+/// Return contacts of desk phone XML phonebook
+///
+/// - Yealink and Cisco: "DirectoryEntry" of "Name" and "Telephone"
+/// - Yealink: "Unit" of "Name", "Phone1".."Phone3" and "contact" of "display_name", "office_number"..
+/// - Grandstream: "Contact" of "LastName", "FirstName", "Company" and "Phone" of "phonenumber"
+pub fn xml_phonebook_contacts(xml: &str) -> Vec<ContactParts> {
+    let mut contacts: Vec<ContactParts> = Vec::new();
+    for (_, entry) in xml_elements(xml, "DirectoryEntry") {
+        let mut parts = ContactParts::default();
+        parts.set("name", &xml_tag_text(entry, "Name").unwrap_or_default());
+        for (attrs, number) in xml_elements(entry, "Telephone") {
+            let label = xml_attr(attrs, "label").unwrap_or_default();
+            let teltype = teltype_of_label(&label).unwrap_or("WORK");
+            parts.set(&format!("{}{}", TEL_FIELD_PREFIX, teltype), &xml_parser::xml_unescape(number));
+        }
+        contacts.push(parts);
+    }
+    for (attrs, _) in xml_elements(xml, "Unit").into_iter().chain(xml_elements(xml, "contact")) {
+        let mut parts = ContactParts::default();
+        for name_attr in ["Name", "display_name"] {
+            parts.set("name", &xml_attr(attrs, name_attr).unwrap_or_default());
+        }
+        parts.set("categories", &xml_attr(attrs, "group_id_name").unwrap_or_default());
+        for (attr, teltype) in YEALINK_PHONE_ATTRS {
+            let number = xml_attr(attrs, attr).unwrap_or_default();
+            if !number.trim().is_empty() {
+                parts.tels.push((teltype.to_string(), number.trim().to_string()));
+            }
+        }
+        contacts.push(parts);
+    }
+    for (_, entry) in xml_elements(xml, "Contact") {
+        let mut parts = ContactParts::default();
+        parts.set("last", &xml_tag_text(entry, "LastName").unwrap_or_default());
+        parts.set("first", &xml_tag_text(entry, "FirstName").unwrap_or_default());
+        parts.set("org", &xml_tag_text(entry, "Company").unwrap_or_default());
+        for (attrs, phone) in xml_elements(entry, "Phone") {
+            let label = xml_attr(attrs, "type").unwrap_or_default();
+            let teltype = teltype_of_label(&label).unwrap_or("WORK");
+            parts.set(&format!("{}{}", TEL_FIELD_PREFIX, teltype), &xml_tag_text(phone, "phonenumber").unwrap_or_default());
+        }
+        contacts.push(parts);
+    }
+    contacts
+}
+
+/// Convert input file to vcf file, return count of contacts and format
+///
+/// Format "csv" is AGEphone CSV if it looks so.
+pub fn input_to_vcf(
+    format: &str, filename: &str, csv_options: &CsvOptions, vcf_filename: &str
+) -> Result<(usize, &'static str), i32> {
+    let (contacts, format) = match format {
+        INPUT_FORMAT_XML => {
+            let Ok(bytes) = read(filename) else {
+                return Err(_ERR_READ_FILE);
+            };
+            let xml = String::from_utf8_lossy(&bytes);
+            (xml_phonebook_contacts(xml.trim_start_matches('\u{feff}')), INPUT_FORMAT_XML)
+        },
+        _ => {
+            let rows = read_csv(filename, &csv_options.encoding)?;
+            if format == INPUT_FORMAT_AGEPHONE || csv_options.map.is_empty() && is_agephone_csv(&rows) {
+                (agephone_contacts(&rows), INPUT_FORMAT_AGEPHONE)
+            } else {
+                (csv_contacts(&rows, csv_options)?, INPUT_FORMAT_CSV)
+            }
+        },
+    };
+    let text: String = contacts.iter().map(|c| c.to_vcard()).collect();
+    if write(vcf_filename, text).is_err() {
+        return Err(_ERR_WRITE_FILE);
+    }
+    Ok((contacts.len(), format))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn tels(parts: &ContactParts) -> Vec<(String, String)> {
+        let vcard = parts.to_vcard();
+        Contact::new(&vcard).tel_iter().map(|t| (t.teltype().to_string(), t.number().to_string())).collect()
+    }
+
+    fn pairs(items: &[(&str, &str)]) -> Vec<(String, String)> {
+        items.iter().map(|(t, n)| (t.to_string(), n.to_string())).collect()
+    }
+
+    #[test]
+    fn test_agephone_contacts() {
+        let rows = parse_csv(
+            "や - 山田 (HOME),03-1111-2222,Family,やまだ たろう,2\r\n\
+            や - 山田 (HOME),090-1111-2222,Family,やまだ たろう,3\r\n\
+            Reception,201,,,1\r\n",
+            ',',
+        );
+        assert!(is_agephone_csv(&rows));
+        let contacts = agephone_contacts(&rows);
+        assert_eq!(2, contacts.len());
+        assert_eq!("や - 山田 (HOME)", contacts[0].name);
+        assert_eq!(vec!["Family".to_string()], contacts[0].categories);
+        assert_eq!(pairs(&[("HOME", "03-1111-2222"), ("CELL", "090-1111-2222")]), tels(&contacts[0]));
+        assert_eq!(pairs(&[("", "201")]), tels(&contacts[1]));
+        assert!(!is_agephone_csv(&parse_csv("name,tel,a,b,type\r\nA,1,,,2\r\n", ',')));
+    }
+
+    #[test]
+    fn test_xml_phonebook_contacts() {
+        let yealink = r#"<YealinkIPPhoneDirectory>
+            <DirectoryEntry><Name>Bob &amp; Co</Name><Telephone>100</Telephone><Telephone label="Mobile">090-1</Telephone></DirectoryEntry>
+            </YealinkIPPhoneDirectory>"#;
+        let contacts = xml_phonebook_contacts(yealink);
+        assert_eq!("Bob & Co", contacts[0].name);
+        assert_eq!(pairs(&[("WORK", "100"), ("CELL", "090-1")]), tels(&contacts[0]));
+        let remote = r#"<root_group><Unit Name="Alice" Phone1="101" Phone2="" Phone3="9" default_photo=""/></root_group>
+            <root_contact><contact display_name="Carol" office_number="102" mobile_number="090-2" group_id_name="Sales"/></root_contact>"#;
+        let contacts = xml_phonebook_contacts(remote);
+        assert_eq!(vec!["Alice", "Carol"], contacts.iter().map(|c| c.name.as_str()).collect::<Vec<&str>>());
+        assert_eq!(pairs(&[("WORK", "101"), ("", "9")]), tels(&contacts[0]));
+        assert_eq!(pairs(&[("WORK", "102"), ("CELL", "090-2")]), tels(&contacts[1]));
+        let grandstream = r#"<?xml version="1.0" encoding="UTF-8"?><AddressBook><Contact>
+            <LastName>Smith</LastName><FirstName>John</FirstName><Company>ACME</Company>
+            <Phone type="Work"><phonenumber>1001</phonenumber><accountindex>1</accountindex></Phone>
+            <Phone type="Home"><phonenumber>03-1</phonenumber><accountindex>1</accountindex></Phone>
+            <Group>1</Group></Contact></AddressBook>"#;
+        let contacts = xml_phonebook_contacts(grandstream);
+        assert_eq!(1, contacts.len());
+        assert_eq!("Smith John", Contact::new(&contacts[0].to_vcard()).full_name());
+        assert_eq!(pairs(&[("WORK", "1001"), ("HOME", "03-1")]), tels(&contacts[0]));
+    }
+}
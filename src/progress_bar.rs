@@ -1,38 +1,57 @@
+use crate::logger;
+
+use logger::*;
+
+const BAR_W_MIN: usize = 10;
+
 pub struct ProgressBar {
     title: String,
     max: usize,
     pg: usize,
     width: usize,
     end_str_len: usize,
+    last_per: usize,
+    is_hidden: bool,
+    is_done: bool,
 }
 
 impl ProgressBar {
 
-    /// Initialize progress bar
+    /// Initialize progress bar, hidden if stderr is not terminal or quiet
     pub fn new(title: &str, max: usize) -> Self {
-        const _CONSOLE_W_MAX: usize = 80;
         let title = format!("> {} [", title);
         let end_str_len = max.to_string().len() * 2 + 3; // "] max/max"
-        let width = _CONSOLE_W_MAX - title.len() - end_str_len;
-        Self{ title, max, pg: 0, width, end_str_len }
+        // Keep last column empty, not to wrap the line
+        let width = terminal_width()
+            .saturating_sub(title.len() + end_str_len + 1)
+            .max(BAR_W_MIN);
+        let is_hidden = !is_progress_visible();
+        Self{ title, max, pg: 0, width, end_str_len, last_per: usize::MAX, is_hidden, is_done: false }
     }
 
-    /// Stretch this bar
+    /// Stretch this bar, done line is printed only once even if over max
     pub fn progress(&mut self) {
         self.pg += 1;
+        if self.is_hidden || self.is_done {
+            return;
+        }
         let is_done = self.max <= self.pg;
         let per = if !is_done {
             (self.pg as f32 / self.max as f32 * self.width as f32).ceil() as usize
         } else {
             self.width
         };
-        print!("\r \r{}", &self.title);
+        // Redraw only when the bar is stretched, for large address book
+        if per == self.last_per && !is_done {
+            return;
+        }
+        self.last_per = per;
+        let mut line = format!("\r \r{}", &self.title);
         for i in 1..self.width {
-            let c = if i < per {"#"} else {" "};
-            print!("{}", c);
+            line.push(if i < per {'#'} else {' '});
         }
         if !is_done {
-            print!("] {}/{}", self.pg, self.max);
+            eprint!("{}] {}/{}", line, self.pg, self.max);
         } else {
             let done_str = "] Done!";
             let spc = if self.end_str_len < done_str.len() {
@@ -40,7 +59,8 @@ impl ProgressBar {
             } else {
                 self.end_str_len - done_str.len()
             };
-            println!("{msg}{:1$}", " ", spc, msg = done_str);
+            eprintln!("{}{msg}{:2$}", line, " ", spc, msg = done_str);
+            self.is_done = true;
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,247 @@
+use crate::call_log;
+use crate::error_flg;
+use crate::vcf_parser;
+use crate::xml_parser;
+
+use std::collections::HashMap;
+use std::fs::write;
+use std::io::{BufRead, Write};
+use call_log::number_domain_key;
+use error_flg::*;
+use vcf_parser::*;
+use xml_parser::SipContacts;
+
+const PAGE_SIZE: usize = 20;
+const REVIEW_HELP: &str = "\
+l [PAGE]\t... List contacts, 20 per page.
+s [TEXT]\t... Search name, reading or number. No TEXT lists all.
+n CARD [NAME]\t... Change name. No NAME restores original.
+t CARD TEL\t... Toggle number on or off.
+c\t\t... Show numbers same as other contact.
+w\t\t... Write and exit.
+q\t\t... Exit without writing.";
+
+/// One vcf card under review
+struct ReviewCard {
+    vcard: String,
+    contact: Contact,
+    name: Option<String>,
+    off: Vec<usize>, // indexes of tel_iter
+}
+
+/// Interactive review of contacts before writing
+///
+/// Edited cards are written to vcf, and converted as usual.
+pub struct Review {
+    name_pattern: String,
+    dial_format: String,
+    cards: Vec<ReviewCard>,
+    found: Vec<usize>, // indexes of cards, by search
+}
+
+fn say(out: &mut impl Write, text: &str) -> Result<(), i32> {
+    writeln!(out, "{}", text).map_err(|_| _ERR_WRITE_FILE)
+}
+
+impl Review {
+
+    /// Read all cards of vcf
+    pub fn new(vcf: &Vcf, name_pattern: &str, dial_format: &str) -> Result<Self, i32> {
+        let mut cards: Vec<ReviewCard> = Vec::new();
+        for vcard in vcf.reader()? {
+            let vcard = vcard?;
+            let contact = Contact::new(&vcard);
+            cards.push(ReviewCard { vcard, contact, name: None, off: Vec::new() });
+        }
+        let found = (0..cards.len()).collect();
+        Ok(Self { name_pattern: name_pattern.to_string(), dial_format: dial_format.to_string(), cards, found })
+    }
+
+    /// Run commands until "w" or "q", return true to write
+    ///
+    /// End of input is same as "q".
+    pub fn run(&mut self, input: impl BufRead, out: &mut impl Write, sip_contacts: &SipContacts) -> Result<bool, i32> {
+        self.list(out, 1)?;
+        say(out, "h for help.")?;
+        let prompt = |out: &mut dyn Write| write!(out, "> ").and_then(|_| out.flush()).map_err(|_| _ERR_WRITE_FILE);
+        prompt(out)?;
+        for line in input.lines() {
+            let Ok(line) = line else {
+                return Err(_ERR_READ_FILE);
+            };
+            let line = line.trim();
+            let (cmd, rest) = line.split_once(' ').map_or((line, ""), |(c, r)| (c, r.trim()));
+            match cmd {
+                "" => (),
+                "l" => self.list(out, rest.parse().unwrap_or(1))?,
+                "s" => {
+                    self.search(rest);
+                    self.list(out, 1)?;
+                },
+                "n" => self.rename(out, rest)?,
+                "t" => self.toggle(out, rest)?,
+                "c" => self.collisions(out, sip_contacts)?,
+                "w" => return Ok(true),
+                "q" => return Ok(false),
+                _ => say(out, REVIEW_HELP)?,
+            }
+            prompt(out)?;
+        }
+        Ok(false)
+    }
+
+    /// Return (renamed cards, numbers turned off)
+    pub fn edit_counts(&self) -> (usize, usize) {
+        (
+            self.cards.iter().filter(|c| c.name.is_some()).count(),
+            self.cards.iter().map(|c| c.off.len()).sum(),
+        )
+    }
+
+    /// Write edited cards to vcf file
+    pub fn write_vcf(&self, filename: &str) -> Result<(), i32> {
+        let text: String = self.cards.iter()
+            .map(|c| Contact::edited_vcard(&c.vcard, c.name.as_deref(), &c.off))
+            .collect();
+        write(filename, text).map_err(|_| _ERR_WRITE_FILE)
+    }
+
+    /// Return formatted names and numbers to dial of card
+    fn entries(&self, card: &ReviewCard) -> Vec<(String, String)> {
+        let ct = &card.contact;
+        let (finitial, linitial) = (ct.finitial(), ct.linitial());
+        ct.tel_iter().map(|tel| (
+            ct.fmt_name(&self.name_pattern, &finitial, &linitial, tel.teltype()),
+            tel.dial(&self.dial_format),
+        )).collect()
+    }
+
+    fn print_card(&self, out: &mut impl Write, idx: usize) -> Result<(), i32> {
+        let card = &self.cards[idx];
+        let mark = if card.name.is_some() { " *" } else { "" };
+        say(out, &format!("{:>4} {}{}", idx + 1, card.contact.full_name(), mark))?;
+        for (tel_idx, (name, number)) in self.entries(card).into_iter().enumerate() {
+            let on = if card.off.contains(&tel_idx) { " " } else { "x" };
+            say(out, &format!("     {:>2} [{}] {}  {}", tel_idx + 1, on, name, number))?;
+        }
+        Ok(())
+    }
+
+    fn list(&self, out: &mut impl Write, page: usize) -> Result<(), i32> {
+        let pages = self.found.len().div_ceil(PAGE_SIZE).max(1);
+        let page = page.clamp(1, pages);
+        for idx in self.found.iter().skip((page - 1) * PAGE_SIZE).take(PAGE_SIZE) {
+            self.print_card(out, *idx)?;
+        }
+        say(out, &format!("Page {}/{}, {} of {} contacts.", page, pages, self.found.len(), self.cards.len()))
+    }
+
+    fn search(&mut self, text: &str) {
+        let text = text.to_lowercase();
+        self.found = (0..self.cards.len()).filter(|i| {
+            let ct = &self.cards[*i].contact;
+            let reading = ct.last_hira_fullname() + &ct.first_hira_fullname();
+            ct.full_name().to_lowercase().contains(&text)
+                || reading.to_lowercase().contains(&text)
+                || ct.tel_iter().any(|t| t.number().contains(&text))
+        }).collect();
+    }
+
+    /// Return index of card from 1 based number
+    fn card_index(&self, text: &str) -> Option<usize> {
+        text.parse::<usize>().ok().filter(|n| (1..=self.cards.len()).contains(n)).map(|n| n - 1)
+    }
+
+    fn rename(&mut self, out: &mut impl Write, args: &str) -> Result<(), i32> {
+        let (card_no, name) = args.split_once(' ').map_or((args, ""), |(c, n)| (c, n.trim()));
+        let Some(idx) = self.card_index(card_no) else {
+            return say(out, "No such card.");
+        };
+        let card = &mut self.cards[idx];
+        card.name = (!name.is_empty()).then(|| name.to_string());
+        card.contact = Contact::new(&Contact::edited_vcard(&card.vcard, card.name.as_deref(), &[]));
+        self.print_card(out, idx)
+    }
+
+    fn toggle(&mut self, out: &mut impl Write, args: &str) -> Result<(), i32> {
+        let mut words = args.split_whitespace();
+        let Some(idx) = words.next().and_then(|w| self.card_index(w)) else {
+            return say(out, "No such card.");
+        };
+        let card = &mut self.cards[idx];
+        let tel_count = card.contact.tel_iter().count();
+        let Some(tel_idx) = words.next().and_then(|w| w.parse::<usize>().ok()).filter(|n| (1..=tel_count).contains(n)) else {
+            return say(out, "No such number.");
+        };
+        match card.off.iter().position(|i| *i == tel_idx - 1) {
+            Some(pos) => { card.off.remove(pos); },
+            None => card.off.push(tel_idx - 1),
+        }
+        self.print_card(out, idx)
+    }
+
+    /// Show numbers in Contacts.xml of other name, and in other vcf card
+    fn collisions(&self, out: &mut impl Write, sip_contacts: &SipContacts) -> Result<(), i32> {
+        let mut written: HashMap<String, (usize, String)> = HashMap::new(); // number key => card, name
+        let mut count = 0;
+        for (idx, card) in self.cards.iter().enumerate() {
+            for (tel_idx, (name, number)) in self.entries(card).into_iter().enumerate() {
+                if card.off.contains(&tel_idx) { continue; }
+                for (old_number, old_name) in sip_contacts.find(&number) {
+                    if old_name == name { continue; }
+                    say(out, &format!("{:>4} {}  {}  replaces \"{}\" of Contacts.xml", idx + 1, name, old_number, old_name))?;
+                    count += 1;
+                }
+                let key = number_domain_key(&number);
+                if key.is_empty() { continue; }
+                if let Some((other_idx, other_name)) = written.insert(key, (idx, name.clone())) {
+                    say(out, &format!("{:>4} {}  {}  also in card {} \"{}\"", idx + 1, name, number, other_idx + 1, other_name))?;
+                    count += 1;
+                }
+            }
+        }
+        say(out, &format!("{} same numbers.", count))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::arg_and_help::{ARG_PAT_DEFAULT, DIAL_FORMAT_DEFAULT};
+
+    #[test]
+    fn test_review() {
+        let vcf = Vcf::new("testfiles/test.vcf").unwrap();
+        let scs = SipContacts::from_reader(
+            "<contact name=\"Old\" number=\"1234\" firstname=\"\"/>\r\n".as_bytes()
+        );
+        let mut review = Review::new(&vcf, ARG_PAT_DEFAULT, DIAL_FORMAT_DEFAULT).unwrap();
+        let input = "s yamada\nn 1 Hanako Yamada\nt 1 2\nt 1 9\nc\nbad\nw\nq\n";
+        let mut out: Vec<u8> = Vec::new();
+        assert!(review.run(input.as_bytes(), &mut out, &scs).unwrap());
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("1 of 3 contacts."));
+        assert!(out.contains("   1 Hanako Yamada *\n"));
+        assert!(out.contains("      2 [ ] Y - Hanako Yamada (WORK)  1234\n"));
+        assert!(out.contains("No such number."));
+        assert!(out.contains("0 same numbers."));
+        assert!(out.contains("q\t\t... Exit without writing."));
+        assert_eq!((1, 1), review.edit_counts());
+
+        let dir = std::env::temp_dir().join(format!("vcf2msip_test_review_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let filename = dir.join("review.vcf").to_str().unwrap().to_string();
+        review.write_vcf(&filename).unwrap();
+        let cts: Vec<Contact> = Vcf::new(&filename).unwrap().reader().unwrap().map(|v| Contact::new(&v.unwrap())).collect();
+        assert_eq!(3, cts.len());
+        assert_eq!("Hanako Yamada", cts[0].full_name());
+        assert!(cts[0].tel_iter().all(|t| t.number() != "1234"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // End of input is quit, and collision with Contacts.xml
+        let mut review = Review::new(&vcf, ARG_PAT_DEFAULT, DIAL_FORMAT_DEFAULT).unwrap();
+        let mut out: Vec<u8> = Vec::new();
+        assert!(!review.run("c\n".as_bytes(), &mut out, &scs).unwrap());
+        assert!(String::from_utf8(out).unwrap().contains("1234  replaces \"Old\" of Contacts.xml"));
+    }
+}
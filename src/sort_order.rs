@@ -0,0 +1,213 @@
+use crate::error_flg;
+use crate::vcf_parser;
+
+use kanaria::{string::UCSStr, utils::ConvertTarget};
+use error_flg::*;
+use vcf_parser::*;
+
+/// Keys of sort option
+pub const SORT_KEYS: &[&str] = &["none", "name", "reading", "last", "category", "number"];
+
+/// Output order of contacts
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    #[default]
+    None,
+    Name,
+    Reading,
+    LastName,
+    Category,
+    Number,
+}
+
+/// One collation unit (script class, normalized char)
+type CollateChar = (u8, char);
+
+/// Sort key of one output row
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SortKey(Vec<Vec<CollateChar>>);
+
+/// Rows buffer to write in sorted order
+pub struct SortRows<T> {
+    order: SortOrder,
+    rows: Vec<(SortKey, T)>,
+}
+
+impl SortOrder {
+
+    /// Return sort order from option value
+    pub fn from_arg(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "none" => Some(Self::None),
+            "name" => Some(Self::Name),
+            "reading" => Some(Self::Reading),
+            "last" => Some(Self::LastName),
+            "category" => Some(Self::Category),
+            "number" => Some(Self::Number),
+            _ => None,
+        }
+    }
+}
+
+impl SortKey {
+
+    /// Make key from parsed vcf contact
+    pub fn from_contact(order: SortOrder, ct: &Contact, new_name: &str, number: &str) -> Self {
+        let name = collate_key(new_name);
+        match order {
+            SortOrder::None => Self::default(),
+            SortOrder::Name => Self(vec![name]),
+            SortOrder::Reading => Self(vec![collate_key(&ct.hira_reading()), name]),
+            SortOrder::LastName => Self(vec![collate_key(&ct.last_name_reading()), name]),
+            SortOrder::Category => Self(vec![
+                category_key(&ct.first_categories()),
+                name,
+            ]),
+            SortOrder::Number => Self(vec![number_key(number), name]),
+        }
+    }
+
+    /// Make key from name and number only (merged MicroSIP contact)
+    pub fn from_name(order: SortOrder, name: &str, number: &str) -> Self {
+        let name_key = collate_key(name);
+        match order {
+            SortOrder::None => Self::default(),
+            SortOrder::Name | SortOrder::Reading | SortOrder::LastName => Self(vec![name_key]),
+            SortOrder::Category => Self(vec![category_key(""), name_key]),
+            SortOrder::Number => Self(vec![number_key(number), name_key]),
+        }
+    }
+}
+
+impl<T> SortRows<T> {
+
+    pub fn new(order: SortOrder) -> Self {
+        Self { order, rows: Vec::new() }
+    }
+
+    /// Stock row to sort, or return it back to write now if not sorted
+    pub fn push(&mut self, key: SortKey, row: T) -> Option<T> {
+        if self.order == SortOrder::None {
+            return Some(row);
+        }
+        self.rows.push((key, row));
+        None
+    }
+
+    /// Return stocked rows, stable sorted
+    pub fn into_sorted(mut self) -> impl Iterator<Item = T> {
+        self.rows.sort_by(|a, b| a.0.cmp(&b.0));
+        self.rows.into_iter().map(|(_, row)| row)
+    }
+}
+
+/// Return collation key
+///
+/// Zenkaku alphanumerics are folded to Hankaku, Katakana to Hiragana and
+/// Latin to lower case. Characters are then ordered by script class:
+/// digits, Latin, Kana, and others (Kanji etc.), so mixed Japanese and
+/// Latin names are grouped instead of interleaved by code point.
+pub fn collate_key(s: &str) -> Vec<CollateChar> {
+    let hira = UCSStr::from_str(s)
+        .narrow(ConvertTarget::NUMBER | ConvertTarget::ALPHABET)
+        .wide(ConvertTarget::KATAKANA)
+        .hiragana()
+        .to_string();
+    hira.chars()
+        .filter(|c| !c.is_whitespace() && !is_ignorable_symbol(*c))
+        .flat_map(|c| c.to_lowercase())
+        .map(|c| (script_class(c), c))
+        .collect()
+}
+
+/// Category key, empty category goes last
+fn category_key(category: &str) -> Vec<CollateChar> {
+    if category.is_empty() {
+        vec![(u8::MAX, '\0')]
+    } else {
+        collate_key(category)
+    }
+}
+
+/// Number key by numeric only, keep length order for same prefix
+fn number_key(number: &str) -> Vec<CollateChar> {
+    fix_number(number).chars().map(|c| (0, c)).collect()
+}
+
+/// Return order of script
+fn script_class(c: char) -> u8 {
+    match c {
+        '0'..='9' => 0,
+        'a'..='z' => 1,
+        '\u{3041}'..='\u{309F}' | '\u{30FC}' => 2, // Hiragana, prolonged sound mark
+        _ if c.is_ascii() => 3,
+        _ => 4,
+    }
+}
+
+/// Symbols to skip at compare
+fn is_ignorable_symbol(c: char) -> bool {
+    matches!(c, '-' | '_' | '.' | ',' | '(' | ')' | '[' | ']' | '・' | '（' | '）' | '「' | '」')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sorted(order: SortOrder, names: &[&str]) -> Vec<String> {
+        let mut rows = SortRows::new(order);
+        let mut passed: Vec<String> = Vec::new();
+        for name in names {
+            if let Some(row) = rows.push(SortKey::from_name(order, name, ""), name.to_string()) {
+                passed.push(row);
+            }
+        }
+        passed.extend(rows.into_sorted());
+        passed
+    }
+
+    #[test]
+    fn test_collate_mixed_names() {
+        let names = ["やまだ", "Zeta", "ｱｵｷ", "alpha", "カトウ", "１０１", "山田"];
+        assert_eq!(
+            vec!["１０１", "alpha", "Zeta", "ｱｵｷ", "カトウ", "やまだ", "山田"],
+            sorted(SortOrder::Name, &names)
+        );
+        // Keep input order if not specified
+        assert_eq!(names.to_vec(), sorted(SortOrder::None, &names));
+    }
+
+    #[test]
+    fn test_sort_stable() {
+        let mut rows = SortRows::new(SortOrder::Name);
+        rows.push(SortKey::from_name(SortOrder::Name, "B", "2"), 1);
+        rows.push(SortKey::from_name(SortOrder::Name, "a", "1"), 2);
+        rows.push(SortKey::from_name(SortOrder::Name, "b", "3"), 3);
+        assert_eq!(vec![2, 1, 3], rows.into_sorted().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_sort_reading() {
+        let cards = [
+            ("Hanako Yamada", "BEGIN:VCARD\nFN:山田 花子\nX-PHONETIC-LAST-NAME:ヤマダ\nX-PHONETIC-FIRST-NAME:ハナコ\n"),
+            ("Suzuki", "BEGIN:VCARD\nFN:すずき\n"),
+            ("Yamada", "BEGIN:VCARD\nFN:やまだ\n"),
+            ("Abe", "BEGIN:VCARD\nFN:Abe\nX-PHONETIC-LAST-NAME:わたなべ\n"),
+        ];
+        let mut rows = SortRows::new(SortOrder::Reading);
+        for (name, vcard) in cards {
+            rows.push(SortKey::from_contact(SortOrder::Reading, &Contact::new(vcard), name, ""), name);
+        }
+        // Card without phonetic name is sorted by its name once, same as reading
+        assert_eq!(vec!["Suzuki", "Yamada", "Hanako Yamada", "Abe"], rows.into_sorted().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_sort_number() {
+        let mut rows = SortRows::new(SortOrder::Number);
+        for (name, number) in [("A", "03-1111"), ("B", "0120-22"), ("C", "03-0000")] {
+            rows.push(SortKey::from_name(SortOrder::Number, name, number), name);
+        }
+        assert_eq!(vec!["B", "C", "A"], rows.into_sorted().collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,273 @@
+use crate::error_flg;
+use crate::arg_and_help;
+use crate::vcf_parser;
+use crate::json_writer;
+use crate::call_log;
+
+use std::collections::HashMap;
+use encoding_rs::SHIFT_JIS;
+use error_flg::*;
+use arg_and_help::*;
+use vcf_parser::*;
+use json_writer::*;
+use call_log::{is_sip_uri, number_domain_key};
+
+pub const CHECK_MAX_WIDTH_DEFAULT: usize = 40;
+const KNOWN_TEL_TYPES: &[&str] = &[
+    "", "HOME", "CELL", "WORK", "MAIN", "VOICE", "FAX", "PAGER", "OTHER", "IPHONE", "SIP", "携帯",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueLevel {
+    Error,
+    Warning,
+}
+
+/// One problem of a card
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    level: IssueLevel,
+    code: &'static str,
+    message: String,
+}
+
+/// Problems of one card, index 0 is for the text out of cards
+#[derive(Debug, Default)]
+pub struct CardReport {
+    index: usize,
+    line_no: usize,
+    name: String,
+    issues: Vec<Issue>,
+}
+
+/// Result of checking whole vcf
+#[derive(Debug, Default)]
+pub struct CheckResult {
+    cards: usize,
+    reports: Vec<CardReport>,
+}
+
+impl Issue {
+    fn error(code: &'static str, message: String) -> Self {
+        Self { level: IssueLevel::Error, code, message }
+    }
+    fn warning(code: &'static str, message: String) -> Self {
+        Self { level: IssueLevel::Warning, code, message }
+    }
+    fn level_str(&self) -> &'static str {
+        match self.level {
+            IssueLevel::Error => "ERROR",
+            IssueLevel::Warning => "WARNING",
+        }
+    }
+}
+
+impl CheckResult {
+
+    pub fn count(&self, level: IssueLevel) -> usize {
+        self.reports.iter()
+            .flat_map(|r| r.issues.iter())
+            .filter(|i| i.level == level)
+            .count()
+    }
+
+    /// Print human readable report
+    pub fn print(&self) {
+        for rep in &self.reports {
+            if rep.index == 0 {
+                println!("vcf:");
+            } else {
+                println!("card {} (line {}): {}", rep.index, rep.line_no, rep.name);
+            }
+            for issue in &rep.issues {
+                println!("  {}: [{}] {}", issue.level_str(), issue.code, issue.message);
+            }
+        }
+        println!(
+            "CHECKED [Card:{}, Error:{}, Warning:{}]",
+            self.cards, self.count(IssueLevel::Error), self.count(IssueLevel::Warning)
+        );
+    }
+
+    /// Return machine readable report
+    pub fn to_json(&self, filename: &str) -> JsonValue {
+        let reports = self.reports.iter().map(|rep| {
+            let issues = rep.issues.iter().map(|i| JsonValue::object(vec![
+                ("level", JsonValue::str(&i.level_str().to_lowercase())),
+                ("code", JsonValue::str(i.code)),
+                ("message", JsonValue::str(&i.message)),
+            ])).collect();
+            JsonValue::object(vec![
+                ("card", if rep.index == 0 { JsonValue::Null } else { JsonValue::num(rep.index) }),
+                ("line", JsonValue::num(rep.line_no)),
+                ("name", JsonValue::str(&rep.name)),
+                ("issues", JsonValue::Array(issues)),
+            ])
+        }).collect();
+        JsonValue::object(vec![
+            ("file", JsonValue::str(filename)),
+            ("cards", JsonValue::num(self.cards)),
+            ("errors", JsonValue::num(self.count(IssueLevel::Error))),
+            ("warnings", JsonValue::num(self.count(IssueLevel::Warning))),
+            ("reports", JsonValue::Array(reports)),
+        ])
+    }
+}
+
+/// Process of "check" subcommand, nothing is written
+pub fn run_check(args: &Args) -> Result<(), i32> {
+    let vcf = Vcf::new(args.load_file_name())?;
+    let res = check_vcf(&vcf, args.name_pattern_normal(), args.check_max_width())?;
+    if args.is_json() {
+        println!("{}", res.to_json(args.load_file_name()));
+    } else {
+        res.print();
+    }
+    if res.count(IssueLevel::Error) > 0 {
+        return Err(_ERR_CHECK_FOUND_ERRORS);
+    }
+    Ok(())
+}
+
+/// Check all cards in vcf
+pub fn check_vcf(vcf: &Vcf, name_pattern: &str, max_width: usize) -> Result<CheckResult, i32> {
+    let mut res = CheckResult::default();
+    let mut numbers: HashMap<String, (usize, String)> = HashMap::new(); // number => card index, name
+    let mut reader = vcf.reader()?;
+    while let Some(vcard) = reader.next() {
+        res.cards += 1;
+        let ct = Contact::new(&vcard?);
+        let mut rep = CardReport {
+            index: res.cards,
+            line_no: reader.card_line_no(),
+            name: ct.full_name().to_string(),
+            issues: check_contact(&ct, name_pattern, max_width),
+        };
+        // Duplicate numbers across people
+        for tel in ct.tel_iter() {
+            let fixed = number_domain_key(&tel.dial(DIAL_FORMAT_DEFAULT));
+            if fixed.is_empty() { continue; }
+            match numbers.get(&fixed) {
+                Some((idx, name)) if *idx != rep.index => rep.issues.push(Issue::error(
+                    "DUPLICATE_NUMBER",
+                    format!("\"{}\" is also in card {} ({}).", tel.dial(DIAL_FORMAT_DEFAULT), idx, name),
+                )),
+                Some(_) => (),
+                None => { numbers.insert(fixed, (rep.index, rep.name.clone())); },
+            }
+        }
+        if !rep.issues.is_empty() {
+            res.reports.push(rep);
+        }
+    }
+    let malformed: Vec<Issue> = reader.malformed().iter().map(|m| Issue::error(
+        "MALFORMED",
+        format!("line {} (offset {}): {}", m.line_no, m.offset, m.reason),
+    )).collect();
+    if !malformed.is_empty() {
+        res.reports.insert(0, CardReport { issues: malformed, ..Default::default() });
+    }
+    Ok(res)
+}
+
+/// Check one contact
+fn check_contact(ct: &Contact, name_pattern: &str, max_width: usize) -> Vec<Issue> {
+    let mut issues: Vec<Issue> = Vec::new();
+    if ct.full_name().is_empty() {
+        issues.push(Issue::error("NO_NAME", "No full name or organization.".to_string()));
+    }
+    if ct.is_empty() {
+        issues.push(Issue::error("NO_NUMBER", "No telephone number.".to_string()));
+    }
+    let finitial = ct.finitial();
+    let linitial = ct.linitial();
+    for tel in ct.tel_iter() {
+        if !is_sip_uri(tel.number()) && tel.number().chars().any(|c| !is_dial_char(c)) {
+            issues.push(Issue::error(
+                "NUMBER_NOT_DIALABLE", format!("\"{}\" has letters or symbols.", tel.number())
+            ));
+        }
+        if !KNOWN_TEL_TYPES.iter().any(|t| t.eq_ignore_ascii_case(tel.teltype())) {
+            issues.push(Issue::warning(
+                "UNKNOWN_TEL_TYPE", format!("\"{}\" of \"{}\".", tel.teltype(), tel.number())
+            ));
+        }
+        let new_name = ct.fmt_name(name_pattern, &finitial, &linitial, tel.teltype());
+        let width = display_width(&new_name);
+        if width > max_width {
+            issues.push(Issue::warning(
+                "NAME_TOO_WIDE", format!("\"{}\" is {} columns, over {}.", new_name, width, max_width)
+            ));
+        }
+        if !is_sjis(&new_name) {
+            issues.push(Issue::warning(
+                "NOT_SHIFT_JIS", format!("\"{}\" can't be written to AGEphone CSV.", new_name)
+            ));
+        }
+    }
+    let reading = ct.last_hira_fullname() + " " + &ct.first_hira_fullname();
+    for (what, text) in [("Category", ct.first_categories()), ("Reading", reading)] {
+        if !is_sjis(&text) {
+            issues.push(Issue::warning(
+                "NOT_SHIFT_JIS", format!("{} \"{}\" can't be written to AGEphone CSV.", what, text)
+            ));
+        }
+    }
+    issues
+}
+
+/// Dialable character of telephone number
+fn is_dial_char(c: char) -> bool {
+    c.is_ascii_digit() || matches!(c, '+' | '-' | '(' | ')' | ' ' | '.' | '#' | '*' | ',' | '/')
+}
+
+/// Check encodable by Shift_JIS
+fn is_sjis(s: &str) -> bool {
+    let (_, _, had_errors) = SHIFT_JIS.encode(s);
+    !had_errors
+}
+
+/// Return width in half-width columns, East Asian wide characters are 2
+pub fn display_width(s: &str) -> usize {
+    s.chars().map(|c| match c as u32 {
+        0x1100..=0x115F | 0x2E80..=0x303E | 0x3041..=0x33FF | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF | 0xA000..=0xA4CF | 0xAC00..=0xD7A3 | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F | 0xFF00..=0xFF60 | 0xFFE0..=0xFFE6 | 0x1F300..=0x1FAFF
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }).sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    const TEST_VCF_FILENAME: &str = "testfiles/test.vcf";
+
+    fn codes(issues: &[Issue]) -> Vec<&str> {
+        issues.iter().map(|i| i.code).collect()
+    }
+
+    #[test]
+    fn test_check_vcf() {
+        let vcf = Vcf::new(TEST_VCF_FILENAME).unwrap();
+        let res = check_vcf(&vcf, ARG_PAT_DEFAULT, CHECK_MAX_WIDTH_DEFAULT).unwrap();
+        assert_eq!(3, res.cards);
+        assert_eq!(0, res.count(IssueLevel::Error));
+        // "homeFax" label
+        assert_eq!(1, res.reports.len());
+        assert_eq!(1, res.reports[0].index);
+        assert_eq!(vec!["UNKNOWN_TEL_TYPE"], codes(&res.reports[0].issues));
+    }
+
+    #[test]
+    fn test_check_contact() {
+        let ct = Contact::new("BEGIN:VCARD\nFN:\u{1F600}\nTEL;TYPE=WORK:0120-FLOWER\n");
+        assert_eq!(
+            vec!["NUMBER_NOT_DIALABLE", "NAME_TOO_WIDE", "NOT_SHIFT_JIS", "NOT_SHIFT_JIS"],
+            codes(&check_contact(&ct, ARG_PAT_DEFAULT, 1))
+        );
+        let ct = Contact::new("BEGIN:VCARD\nCATEGORIES:x\n");
+        assert_eq!(vec!["NO_NAME", "NO_NUMBER"], codes(&check_contact(&ct, ARG_PAT_DEFAULT, 40)));
+        assert_eq!(8, display_width("太郎 abc"));
+    }
+}
@@ -2,18 +2,27 @@
 
 use crate::error_flg;
 use crate::arg_and_help;
+use crate::base64;
+use crate::call_log;
+use crate::xml_parser;
 
 pub use std::fs::File;
-pub use std::io::{BufReader, Read};
+pub use std::io::{BufRead, BufReader, Read};
+use std::ops::Range;
+use std::sync::OnceLock;
 use kanaria::{string::UCSStr, utils::ConvertTarget};
 pub use regex::Regex;
 use error_flg::*;
-use arg_and_help::{ARG_PAT_NAME, ARG_PAT_FIRST_INITIAL, ARG_PAT_LAST_INITIAL, ARG_PAT_CATEGORIES, ARG_PAT_TEL_TYPE};
+use base64::base64_decode;
+use call_log::split_ext;
+use xml_parser::xml_escape;
+use arg_and_help::{ARG_PAT_NAME, ARG_PAT_FIRST_INITIAL, ARG_PAT_LAST_INITIAL, ARG_PAT_CATEGORIES, ARG_PAT_TEL_TYPE, ARG_PAT_NUMBER, ARG_PAT_EXT};
 
 #[derive(Debug)]
 pub struct Telephone {
     teltype: String,
-    number: String,
+    number: String, // main number
+    ext: String, // extension or DTMF postdial
 }
 
 #[derive(Debug)]
@@ -24,35 +33,243 @@ pub struct Contact {
     xlast_name: String,
     categories: String,
     organization: String,
+    speed_dial: String,
+    photo: String,
     tel_numbers: Vec<Telephone>,
 }
 
+/// Photo of contact, embedded image or URI
+#[derive(Debug, PartialEq, Eq)]
+pub enum Photo {
+    Image { ext: &'static str, data: Vec<u8> },
+    Uri(String),
+}
+
+/// Image file extensions, by magic bytes and type name
+const IMAGE_TYPES: &[(&[u8], &str, &str)] = &[
+    (b"\xff\xd8", "jpeg", "jpg"),
+    (b"\x89PNG", "png", "png"),
+    (b"GIF8", "gif", "gif"),
+    (b"BM", "bmp", "bmp"),
+];
+
+/// Contact fields from other sources than vcf, to be a vcard
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ContactParts {
+    pub name: String,
+    pub last: String,
+    pub first: String,
+    pub phonetic_last: String,
+    pub phonetic_first: String,
+    pub org: String,
+    pub categories: Vec<String>,
+    pub tels: Vec<(String, String)>, // teltype, number
+}
+
+/// Field names of ContactParts, and "tel.TYPE"
+pub const CONTACT_FIELDS: &[&str] = &["name", "last", "first", "phonetic-last", "phonetic-first", "org", "categories"];
+pub const TEL_FIELD_PREFIX: &str = "tel.";
+/// Telephone type of SIP address without type
+pub const SIP_TEL_TYPE: &str = "SIP";
+
 #[derive(Debug)]
 pub struct Vcf {
-    data: String,
+    filename: String,
+}
+
+/// Streaming vcard reader, yields one vcard text at a time
+///
+/// Card boundaries are "BEGIN:VCARD" and "END:VCARD" lines. Nested vcard of
+/// AGENT property is skipped, and malformed cards are skipped and recorded.
+pub struct VcardReader<R: BufRead> {
+    reader: R,
+    line: String,
+    line_no: usize,
+    offset: u64,
+    depth: usize,
+    card: String,
+    card_line_no: usize,
+    card_offset: u64,
+    is_agent: bool,
+    is_outside_text: bool,
+    malformed: Vec<MalformedCard>,
+}
+
+/// Position and reason of malformed card
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MalformedCard {
+    pub line_no: usize,
+    pub offset: u64,
+    pub reason: &'static str,
+}
+
+/// Compiled patterns for vcard fields
+struct VcardPatterns {
+    full_name: Regex,
+    name: Regex,
+    xfirst_name: Regex,
+    xlast_name: Regex,
+    categories: Regex,
+    organization: Regex,
+    speed_dial: Regex,
+    photo: Regex,
+    tel: Regex,
+    sip: Regex,
+    item_label: Regex,
+}
+
+/// Return patterns, compiled at first call only
+fn patterns() -> &'static VcardPatterns {
+    static PATTERNS: OnceLock<VcardPatterns> = OnceLock::new();
+    PATTERNS.get_or_init(|| {
+        let prefixed = |prefix: &str| Regex::new(&format!("(?m)^{}(.*)$", prefix)).unwrap();
+        VcardPatterns {
+            full_name: prefixed("FN:"),
+            name: prefixed("N:"),
+            xfirst_name: prefixed("X-PHONETIC-FIRST-NAME:"),
+            xlast_name: prefixed("X-PHONETIC-LAST-NAME:"),
+            categories: prefixed("CATEGORIES:"),
+            organization: prefixed(r"item[\d]*.ORG:"),
+            speed_dial: prefixed("X-MICROSIP-SPEEDDIAL:"),
+            photo: Regex::new(r"(?m)^(?:item\d*\.)?PHOTO([;:].*)$").unwrap(),
+            tel: Regex::new(r"(?m)^(item\d)*[\.]*TEL(;TYPE=([a-zA-Z]*))*:(.*)$").unwrap(),
+            sip: Regex::new(r"(?m)^(item\d*)?\.?(IMPP|X-SIP|URL)(;[^:]*)?:(.*)$").unwrap(),
+            item_label: Regex::new(r"(?m)^(item\d*).X-ABLabel:(.*)$").unwrap(),
+        }
+    })
 }
 
 impl Vcf {
 
-    /// Read vcf file
+    /// Open vcf file
     pub fn new(filename: &str) -> Result<Self, i32> {
-        let hfile = match File::open(&filename) {
-            Ok(h) => h,
-            _ => return Err(_ERR_FILE_NOT_FOUND),
-        };
-        let mut reader = BufReader::new(&hfile);
-        let mut vcf = Self { data: String::with_capacity(1048576) };
-        if let Err(_) = reader.read_to_string(&mut vcf.data) {
-            return Err(_ERR_READ_FILE);
+        if File::open(filename).is_err() {
+            return Err(_ERR_FILE_NOT_FOUND);
         }
-        Ok(vcf)
+        Ok(Self { filename: filename.to_string() })
     }
 
-    /// Return split vcards
-    pub fn get_vcards(&self) -> Vec<&str> {
-        let mut block: Vec<&str> = self.data.split("END:VCARD").collect();
-        block.pop();
-        block
+    /// Return streaming vcards reader from top of file
+    pub fn reader(&self) -> Result<VcardReader<BufReader<File>>, i32> {
+        match File::open(&self.filename) {
+            Ok(h) => Ok(VcardReader::new(BufReader::new(h))),
+            _ => Err(_ERR_FILE_NOT_FOUND),
+        }
+    }
+
+    /// Return count of vcards, without parsing
+    pub fn count(&self) -> Result<usize, i32> {
+        let mut count = 0;
+        for vcard in self.reader()? {
+            vcard?;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+impl<R: BufRead> VcardReader<R> {
+
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: String::with_capacity(256),
+            line_no: 0,
+            offset: 0,
+            depth: 0,
+            card: String::new(),
+            card_line_no: 0,
+            card_offset: 0,
+            is_agent: false,
+            is_outside_text: false,
+            malformed: Vec::new(),
+        }
+    }
+
+    /// Return malformed cards found until now
+    pub fn malformed(&self) -> &[MalformedCard] {
+        self.malformed.as_ref()
+    }
+
+    /// Return line number of last read card
+    pub fn card_line_no(&self) -> usize {
+        self.card_line_no
+    }
+
+    fn push_malformed(&mut self, line_no: usize, offset: u64, reason: &'static str) {
+        self.malformed.push(MalformedCard { line_no, offset, reason });
+    }
+
+    /// Start new card from current line
+    fn start_card(&mut self, line_no: usize, offset: u64) {
+        self.card = String::with_capacity(1024);
+        self.card.push_str(&self.line);
+        self.card_line_no = line_no;
+        self.card_offset = offset;
+        self.depth = 1;
+        self.is_agent = false;
+        self.is_outside_text = false;
+    }
+}
+
+impl<R: BufRead> Iterator for VcardReader<R> {
+    type Item = Result<String, i32>;
+
+    /// Read lines until "END:VCARD" of the card
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.line.clear();
+            let len = match self.reader.read_line(&mut self.line) {
+                Ok(len) => len,
+                Err(_) => return Some(Err(_ERR_READ_FILE)),
+            };
+            if len == 0 {
+                if self.depth > 0 {
+                    self.depth = 0;
+                    self.push_malformed(self.card_line_no, self.card_offset, "No END:VCARD at end of file, card skipped");
+                }
+                return None;
+            }
+            self.line_no += 1;
+            let (line_no, offset) = (self.line_no, self.offset);
+            self.offset += len as u64;
+            let text = self.line.trim_end();
+            let is_begin = text.eq_ignore_ascii_case("BEGIN:VCARD");
+            let is_end = text.eq_ignore_ascii_case("END:VCARD");
+            let is_folded = text.starts_with([' ', '\t']);
+            match self.depth {
+                0 if is_begin => self.start_card(line_no, offset),
+                0 if is_end => self.push_malformed(line_no, offset, "END:VCARD without BEGIN:VCARD, ignored"),
+                0 if !text.trim().is_empty() && !self.is_outside_text => {
+                    self.is_outside_text = true;
+                    self.push_malformed(line_no, offset, "Text outside of vcard, ignored");
+                },
+                0 => (),
+                1 if is_begin && self.is_agent => self.depth += 1,
+                1 if is_begin => {
+                    self.push_malformed(self.card_line_no, self.card_offset, "BEGIN:VCARD before END:VCARD, card skipped");
+                    self.start_card(line_no, offset);
+                },
+                1 if is_end => {
+                    self.depth = 0;
+                    return Some(Ok(std::mem::take(&mut self.card)));
+                },
+                1 => {
+                    let upper = text.to_uppercase();
+                    if upper.starts_with("AGENT") && upper.ends_with(":BEGIN:VCARD") {
+                        self.depth += 1; // "AGENT:BEGIN:VCARD"
+                    } else {
+                        self.card.push_str(&self.line);
+                    }
+                    if !is_folded {
+                        self.is_agent = upper.starts_with("AGENT") && upper.ends_with(':');
+                    }
+                },
+                _ if is_begin => self.depth += 1,
+                _ if is_end => self.depth -= 1,
+                _ => (), // Nested vcard of AGENT is not a contact
+            }
+        }
     }
 }
 
@@ -60,22 +277,23 @@ impl Contact {
 
     /// Parse one vcard
     pub fn new(vcard: &str) -> Self {
+        let pat = patterns();
         Self {
-            full_name: Self::capture("FN:", &vcard),
-            name: Self::capture("N:", &vcard),
-            xfirst_name: Self::capture("X-PHONETIC-FIRST-NAME:", &vcard),
-            xlast_name: Self::capture("X-PHONETIC-LAST-NAME:", &vcard),
-            categories: Self::capture("CATEGORIES:", &vcard),
-            organization: Self::capture(r"item[\d]*.ORG:", &vcard),
-            tel_numbers: Self::cap_tel_numbers(&vcard),
+            full_name: Self::capture(&pat.full_name, vcard),
+            name: Self::capture(&pat.name, vcard),
+            xfirst_name: Self::capture(&pat.xfirst_name, vcard),
+            xlast_name: Self::capture(&pat.xlast_name, vcard),
+            categories: Self::capture(&pat.categories, vcard),
+            organization: Self::capture(&pat.organization, vcard),
+            speed_dial: Self::capture(&pat.speed_dial, vcard),
+            photo: Self::capture_folded(&pat.photo, vcard),
+            tel_numbers: Self::cap_tel_numbers(vcard),
         }
     }
 
     /// Capture value from prefixed line
-    fn capture(prefix: &str, vcard: &str) -> String {
-        let pat = format!("(?m)^{}(.*)$", &prefix);
-        let re = Regex::new(&pat).unwrap();
-        let raw_value = match re.captures(&vcard) {
+    fn capture(re: &Regex, vcard: &str) -> String {
+        let raw_value = match re.captures(vcard) {
             None => "".to_string(),
             Some(s) => s[1].trim_end().to_string(),
         };
@@ -83,35 +301,126 @@ impl Contact {
         UCSStr::from_str(&raw_value).wide(ConvertTarget::KATAKANA).to_string()
     }
 
+    /// Capture parameters and value of property, with folded lines
+    fn capture_folded(re: &Regex, vcard: &str) -> String {
+        let Some(cap) = re.captures(vcard) else {
+            return "".to_string();
+        };
+        let mut value = cap[1].trim_end().to_string();
+        let rest = &vcard[cap.get(0).map_or(0, |m| m.end())..];
+        for line in rest.split('\n').skip(1) {
+            if !line.starts_with([' ', '\t']) { break; }
+            value.push_str(line[1..].trim_end());
+        }
+        value
+    }
+
     /// Capture telephones type and number
     fn cap_tel_numbers(vcard: &str) -> Vec<Telephone> {
-        let re = Regex::new(r"(?m)^(item\d)*[\.]*TEL(;TYPE=([a-zA-Z]*))*:(.*)$").unwrap();
-        let mut vec_telnums:Vec<Telephone> = Vec::new();
-        for cap in re.captures_iter(&vcard) {
+        Self::cap_tel_lines(vcard).into_iter().map(|(tel, _)| tel).collect()
+    }
+
+    /// Capture telephones, with range of the line in vcard
+    fn cap_tel_lines(vcard: &str) -> Vec<(Telephone, Range<usize>)> {
+        let line_range = |m: regex::Match| m.start()..(m.end() + 1).min(vcard.len());
+        let mut vec_telnums:Vec<(Telephone, Range<usize>)> = Vec::new();
+        for cap in patterns().tel.captures_iter(vcard) {
             let number = cap.get(4).map_or("", |m| m.as_str());
             let mut teltype = cap.get(3).map_or("", |m| m.as_str()).to_string();
             if teltype.is_empty() {
                 let item = cap.get(1).map_or("", |m| m.as_str());
-                teltype = Self::find_item_label(&vcard, item);
+                teltype = Self::find_item_label(vcard, item);
             }
-            if number != "" { vec_telnums.push(
+            let (number, ext) = split_ext(number);
+            if !number.is_empty() { vec_telnums.push((
                 Telephone{
-                    teltype: teltype,
-                    number: number.trim_end().to_string(),
-                }
-            ); }
+                    teltype,
+                    number: number.to_string(),
+                    ext: ext.to_string(),
+                },
+                line_range(cap.get(0).unwrap()),
+            )); }
+        }
+        for cap in patterns().sip.captures_iter(vcard) {
+            let Some(number) = Self::sip_number(&cap[2], cap[4].trim_end()) else { continue; };
+            let params = cap.get(3).map_or("", |m| m.as_str());
+            let mut teltype = params.split(';')
+                .find_map(|p| p.strip_prefix("TYPE=").or_else(|| p.strip_prefix("type=")))
+                .and_then(|t| t.split(',').next())
+                .unwrap_or_default().to_string();
+            if teltype.is_empty() {
+                teltype = Self::find_item_label(vcard, cap.get(1).map_or("", |m| m.as_str()));
+            }
+            if teltype.is_empty() {
+                teltype = SIP_TEL_TYPE.to_string();
+            }
+            vec_telnums.push((Telephone{ teltype, number, ext: String::new() }, line_range(cap.get(0).unwrap())));
         }
         vec_telnums
     }
 
+    /// Return vcard with full name changed and telephones removed, for reviewed contact
+    ///
+    /// `removed` are indexes of `tel_iter`. END:VCARD is added.
+    pub fn edited_vcard(vcard: &str, full_name: Option<&str>, removed: &[usize]) -> String {
+        let mut ranges: Vec<Range<usize>> = Self::cap_tel_lines(vcard).into_iter()
+            .enumerate()
+            .filter(|(i, _)| removed.contains(i))
+            .map(|(_, (_, range))| range)
+            .collect();
+        ranges.sort_by_key(|r| r.start);
+        let mut text = String::with_capacity(vcard.len() + 16);
+        let mut pos = 0;
+        for range in ranges {
+            text.push_str(&vcard[pos..range.start]);
+            pos = range.end;
+        }
+        text.push_str(&vcard[pos..]);
+        if let Some(name) = full_name {
+            text = match patterns().full_name.captures(&text).and_then(|c| c.get(1)) {
+                Some(m) => {
+                    let cr = if m.as_str().ends_with('\r') { "\r" } else { "" };
+                    format!("{}{}{}{}", &text[..m.start()], name, cr, &text[m.end()..])
+                },
+                None => {
+                    let at = text.find('\n').map_or(text.len(), |i| i + 1);
+                    format!("{}FN:{}\r\n{}", &text[..at], name, &text[at..])
+                },
+            };
+        }
+        if !text.ends_with('\n') {
+            text.push_str("\r\n");
+        }
+        text.push_str("END:VCARD\r\n");
+        text
+    }
+
+    /// Return SIP URI of IMPP, X-SIP or URL value
+    ///
+    /// IMPP and URL need "sip:" or "sips:" scheme, X-SIP may be "201@pbx.local" only.
+    fn sip_number(property: &str, value: &str) -> Option<String> {
+        let lower = value.to_lowercase();
+        let scheme = ["sip:", "sips:"].into_iter().find(|s| lower.starts_with(s));
+        let user = match scheme {
+            Some(scheme) => &value[scheme.len()..],
+            None if property == "X-SIP" => value,
+            None => return None,
+        };
+        if user.trim().is_empty() {
+            return None;
+        }
+        Some(format!("{}{}", scheme.unwrap_or("sip:"), user.trim()))
+    }
+
     /// Find X-ABLabel for against item
     fn find_item_label(vcard: &str, item: &str) -> String {
-        let pat = format!(r"(?m)^{}.X-ABLabel:(.*)$", item);
-        let re = Regex::new(&pat).unwrap();
-        let res = match re.captures(&vcard) {
-            Some(cap) => cap.get(1).map_or("", |m| m.as_str()),
-            None => "",
-        };
+        if item.is_empty() {
+            return "".to_string();
+        }
+        let res = patterns().item_label
+            .captures_iter(vcard)
+            .find(|cap| &cap[1] == item)
+            .map_or("", |cap| cap.get(2).map_or("", |m| m.as_str()));
         res.trim_end().to_string()
     }
 
@@ -121,7 +430,7 @@ impl Contact {
     }
 
     /// Return full name, or organization name
-    fn full_name(&self) -> &str {
+    pub fn full_name(&self) -> &str {
         if !self.full_name.is_empty() {
             self.full_name.as_ref()
         } else {
@@ -162,6 +471,21 @@ impl Contact {
         v[0].to_string()
     }
 
+    /// Return order of speed dial button, if marked by field or category
+    pub fn speed_dial_order(&self, category: &str) -> Option<usize> {
+        if let Ok(order) = self.speed_dial.trim().parse::<usize>() {
+            return Some(order);
+        }
+        let is_category = !category.is_empty() && self.categories
+            .split(',')
+            .any(|c| c.trim().eq_ignore_ascii_case(category));
+        if is_category || !self.speed_dial.trim().is_empty() {
+            Some(usize::MAX)
+        } else {
+            None
+        }
+    }
+
     /// Return hiragana name from First or Full or Org name
     pub fn first_hira_fullname(&self) -> String {
         self.to_hira_name(&self.xfirst_name)
@@ -172,6 +496,22 @@ impl Contact {
         self.to_hira_name(&self.xlast_name)
     }
 
+    /// Return hiragana of phonetic Last and First name, or Full or Org name if both are empty
+    pub fn hira_reading(&self) -> String {
+        self.to_hira_name(&(self.xlast_name.clone() + &self.xfirst_name))
+    }
+
+    /// Return phonetic last name, or family name, or Full or Org name
+    pub fn last_name_reading(&self) -> String {
+        if !self.xlast_name.is_empty() {
+            return self.xlast_name.clone();
+        }
+        match self.name.split(';').next() {
+            Some(family) if !family.is_empty() => family.to_string(),
+            _ => self.full_name().to_string(),
+        }
+    }
+
     /// Return Japanese HIRAGANA fixed name
     fn to_hira_name(&self, target_name: &str) -> String {
         let target_name = if !target_name.is_empty() {
@@ -184,16 +524,49 @@ impl Contact {
         UCSStr::from_str(&target_name).hiragana().to_string()
     }
 
+    /// Return photo, decoded if embedded
+    ///
+    /// "PHOTO;ENCODING=b;TYPE=JPEG:", "PHOTO;JPEG;ENCODING=BASE64:", "PHOTO:data:image/jpeg;base64," or URI.
+    pub fn photo(&self) -> Option<Photo> {
+        let (params, value) = self.photo.split_once(':')?;
+        let params = params.to_lowercase();
+        let (hint, data) = match value.strip_prefix("data:") {
+            Some(data_uri) => {
+                let (media, data) = data_uri.split_once(',')?;
+                if !media.ends_with(";base64") {
+                    return None;
+                }
+                (media.trim_start_matches("image/").trim_end_matches(";base64").to_lowercase(), data)
+            },
+            None if params.contains("encoding=b") => {
+                let hint = params.split(';')
+                    .map(|p| p.trim_start_matches("type="))
+                    .find(|p| IMAGE_TYPES.iter().any(|(_, name, ext)| p == name || p == ext))
+                    .unwrap_or_default();
+                (hint.to_string(), value)
+            },
+            None if !value.trim().is_empty() => return Some(Photo::Uri(value.trim().to_string())),
+            None => return None,
+        };
+        let data = base64_decode(data).filter(|d| !d.is_empty())?;
+        let ext = IMAGE_TYPES.iter()
+            .find(|(magic, _, _)| data.starts_with(magic))
+            .or_else(|| IMAGE_TYPES.iter().find(|(_, name, ext)| hint == *name || hint == *ext))
+            .map_or("bin", |(_, _, ext)| ext);
+        Some(Photo::Image { ext, data })
+    }
+
     /// Return telephones iterator
     pub fn tel_iter(&self) -> impl Iterator<Item = &Telephone> {
         self.tel_numbers.iter()
     }
 
-    /// Return one xml element line
-    pub fn xml_line(name: &str, number: &str) -> String {
-        let line = r#"<contact name="%name%" number="%number%" firstname="" lastname="" phone="" mobile="" email="" address="" city="" state="" zip="" comment="" id="" info="" presence="0" directory="0"/>"#;
-        line.replace("%name%", name)
-            .replace("%number%", number)
+    /// Return one xml element line, name and number are escaped
+    pub fn xml_line(name: &str, number: &str, presence: bool) -> String {
+        let line = r#"<contact name="%name%" number="%number%" firstname="" lastname="" phone="" mobile="" email="" address="" city="" state="" zip="" comment="" id="" info="" presence="%presence%" directory="0"/>"#;
+        line.replace("%name%", &xml_escape(name))
+            .replace("%number%", &xml_escape(number))
+            .replace("%presence%", if presence { "1" } else { "0" })
     }
 
     /// Return formatted name from pattern
@@ -201,7 +574,7 @@ impl Contact {
         &self, name_pattern: &str, finitial: &str, linitial: &str, teltype: &str
     ) -> String {
         name_pattern
-            .replace(ARG_PAT_NAME, &self.full_name())
+            .replace(ARG_PAT_NAME, self.full_name())
             .replace(ARG_PAT_TEL_TYPE, teltype)
             .replace(ARG_PAT_CATEGORIES, &self.categories)
             .replace(ARG_PAT_FIRST_INITIAL, finitial)
@@ -219,17 +592,99 @@ impl Telephone {
     pub fn number(&self) -> &str {
         self.number.as_ref()
     }
+    pub fn ext(&self) -> &str {
+        self.ext.as_ref()
+    }
+
+    /// Return number to dial, extension is added by format like "%number%,,%ext%"
+    pub fn dial(&self, dial_format: &str) -> String {
+        if self.ext.is_empty() {
+            return self.number.clone();
+        }
+        dial_format.replace(ARG_PAT_NUMBER, &self.number).replace(ARG_PAT_EXT, &self.ext)
+    }
+}
+
+impl ContactParts {
+
+    /// Check field name, TYPE of "tel.TYPE" is alphabet only
+    pub fn is_field(field: &str) -> bool {
+        CONTACT_FIELDS.contains(&field) || field.strip_prefix(TEL_FIELD_PREFIX)
+            .is_some_and(|t| !t.is_empty() && t.chars().all(|c| c.is_ascii_alphabetic()))
+    }
+
+    /// Set value of field, first value only except categories and telephones
+    pub fn set(&mut self, field: &str, value: &str) {
+        let value = value.trim().replace("\r\n", " ").replace(['\r', '\n'], " ");
+        if value.is_empty() {
+            return;
+        }
+        if let Some(teltype) = field.strip_prefix(TEL_FIELD_PREFIX) {
+            self.tels.push((teltype.to_string(), value));
+            return;
+        }
+        let target = match field {
+            "name" => &mut self.name,
+            "last" => &mut self.last,
+            "first" => &mut self.first,
+            "phonetic-last" => &mut self.phonetic_last,
+            "phonetic-first" => &mut self.phonetic_first,
+            "org" => &mut self.org,
+            "categories" => { self.categories.push(value); return; },
+            _ => return,
+        };
+        if target.is_empty() {
+            *target = value;
+        }
+    }
+
+    /// Return vcard, in the form of Contact reads
+    pub fn to_vcard(&self) -> String {
+        let mut vcard = String::from("BEGIN:VCARD\r\nVERSION:3.0\r\n");
+        let name = if self.name.is_empty() {
+            [self.last.as_str(), self.first.as_str()].iter().filter(|s| !s.is_empty()).copied().collect::<Vec<&str>>().join(" ")
+        } else {
+            self.name.clone()
+        };
+        if !name.is_empty() {
+            vcard.push_str(&format!("FN:{}\r\n", name));
+        }
+        if !self.last.is_empty() || !self.first.is_empty() {
+            vcard.push_str(&format!("N:{};{};;;\r\n", self.last, self.first));
+        }
+        for (value, prop) in [
+            (&self.phonetic_last, "X-PHONETIC-LAST-NAME"),
+            (&self.phonetic_first, "X-PHONETIC-FIRST-NAME"),
+            (&self.org, "item1.ORG"),
+        ] {
+            if !value.is_empty() {
+                vcard.push_str(&format!("{}:{}\r\n", prop, value));
+            }
+        }
+        if !self.categories.is_empty() {
+            vcard.push_str(&format!("CATEGORIES:{}\r\n", self.categories.join(",")));
+        }
+        for (teltype, number) in &self.tels {
+            match teltype.as_str() {
+                "" => vcard.push_str(&format!("TEL:{}\r\n", number)),
+                _ => vcard.push_str(&format!("TEL;TYPE={}:{}\r\n", teltype, number)),
+            }
+        }
+        vcard.push_str("END:VCARD\r\n");
+        vcard
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    const TEST_VCF_FILENAME: &'static str = r".\testfiles\test.vcf";
+    const TEST_VCF_FILENAME: &str = "testfiles/test.vcf";
 
     #[test]
     fn test_vcf_parse() {
         let vcf = Vcf::new(TEST_VCF_FILENAME).unwrap();
-        let vcs = vcf.get_vcards();
+        assert_eq!(3, vcf.count().unwrap());
+        let vcs: Vec<String> = vcf.reader().unwrap().map(|v| v.unwrap()).collect();
         let ct = Contact::new(&vcs[0]);
         assert_eq!("Taro Yamada", ct.full_name());
         let tels  = ct.tel_numbers;
@@ -247,4 +702,100 @@ mod test {
         assert_eq!("C", ct.linitial());
         assert_eq!("Business", ct.categories);
     }
+
+    #[test]
+    fn test_speed_dial_order() {
+        let vcf = Vcf::new(TEST_VCF_FILENAME).unwrap();
+        let vcs: Vec<String> = vcf.reader().unwrap().map(|v| v.unwrap()).collect();
+        let ct = Contact::new(&vcs[2]);
+        assert_eq!(Some(usize::MAX), ct.speed_dial_order("business"));
+        assert_eq!(None, ct.speed_dial_order("SpeedDial"));
+        let ct = Contact::new("BEGIN:VCARD\nFN:Reception\nX-MICROSIP-SPEEDDIAL:2\nTEL:201\n");
+        assert_eq!(Some(2), ct.speed_dial_order("SpeedDial"));
+        assert!(Contact::xml_line("A", "1", true).contains(r#"presence="1""#));
+    }
+
+    #[test]
+    fn test_sip_address() {
+        let ct = Contact::new("FN:A\r\nTEL;TYPE=WORK:03-1234\r\n\
+            IMPP;TYPE=work,pref:sip:201@pbx.local\r\n\
+            IMPP:xmpp:alice@example.com\r\n\
+            X-SIP:202@pbx.local\r\n\
+            item1.URL:SIPS:alice@pbx.local;transport=tls\r\nitem1.X-ABLabel:Softphone\r\n\
+            URL:https://example.com\r\n");
+        let tels: Vec<(&str, &str)> = ct.tel_iter().map(|t| (t.teltype(), t.number())).collect();
+        assert_eq!(vec![
+            ("WORK", "03-1234"),
+            ("work", "sip:201@pbx.local"),
+            ("SIP", "sip:202@pbx.local"),
+            ("Softphone", "sips:alice@pbx.local;transport=tls"),
+        ], tels);
+    }
+
+    #[test]
+    fn test_extension() {
+        let ct = Contact::new("FN:A\r\nTEL;TYPE=WORK:03-1234-5678 ext. 205\r\nTEL:+81312345678;ext=206\r\nTEL:090-1111-2222\r\n");
+        let tels: Vec<(&str, &str)> = ct.tel_iter().map(|t| (t.number(), t.ext())).collect();
+        assert_eq!(vec![("03-1234-5678", "205"), ("+81312345678", "206"), ("090-1111-2222", "")], tels);
+        let dials: Vec<String> = ct.tel_iter().map(|t| t.dial("%number%,,%ext%")).collect();
+        assert_eq!(vec!["03-1234-5678,,205", "+81312345678,,206", "090-1111-2222"], dials);
+        assert_eq!("03-1234-5678p205", ct.tel_iter().next().unwrap().dial("%number%p%ext%"));
+    }
+
+    #[test]
+    fn test_edited_vcard() {
+        let vcard = "BEGIN:VCARD\r\nFN:Old\r\nTEL:1\r\nitem1.TEL:2\r\nitem1.X-ABLabel:Desk\r\nX-SIP:201@pbx\r\n";
+        assert_eq!(
+            "BEGIN:VCARD\r\nFN:New Name\r\nitem1.X-ABLabel:Desk\r\nX-SIP:201@pbx\r\nEND:VCARD\r\n",
+            Contact::edited_vcard(vcard, Some("New Name"), &[0, 1])
+        );
+        let ct = Contact::new(&Contact::edited_vcard(vcard, None, &[2]));
+        assert_eq!(vec!["1", "2"], ct.tel_iter().map(|t| t.number()).collect::<Vec<&str>>());
+        assert_eq!("BEGIN:VCARD\nFN:A\r\nTEL:1\r\nEND:VCARD\r\n", Contact::edited_vcard("BEGIN:VCARD\nTEL:1", Some("A"), &[]));
+    }
+
+    #[test]
+    fn test_photo() {
+        // 3.0, folded
+        let ct = Contact::new("BEGIN:VCARD\r\nFN:A\r\nPHOTO;ENCODING=b;TYPE=JPEG:/9j/\r\n 4AAQ\r\nTEL:1\r\n");
+        assert_eq!(Some(Photo::Image { ext: "jpg", data: vec![0xff, 0xd8, 0xff, 0xe0, 0x00, 0x10] }), ct.photo());
+        // 2.1, type by parameter
+        let ct = Contact::new("FN:A\r\nPHOTO;GIF;ENCODING=BASE64:\r\n    AAEC\r\n\r\nTEL:1\r\n");
+        assert_eq!(Some(Photo::Image { ext: "gif", data: vec![0, 1, 2] }), ct.photo());
+        // 4.0 data URI
+        let ct = Contact::new("FN:A\nPHOTO:data:image/png;base64,iVBORw==\n");
+        assert_eq!(Some(Photo::Image { ext: "png", data: b"\x89PNG".to_vec() }), ct.photo());
+        let ct = Contact::new("FN:A\nPHOTO;VALUE=URI:https://example.com/a.jpg\n");
+        assert_eq!(Some(Photo::Uri("https://example.com/a.jpg".to_string())), ct.photo());
+        assert_eq!(None, Contact::new("FN:A\nPHOTO;ENCODING=b:***\n").photo());
+        assert_eq!(None, Contact::new("FN:A\nTEL:1\n").photo());
+    }
+
+    #[test]
+    fn test_vcard_boundaries() {
+        let text = "garbage\r\n\
+            BEGIN:VCARD\r\nFN:A\r\nNOTE:END:VCARD\r\n end:vcard folded\r\nend:vcard\r\n\
+            END:VCARD\r\n\
+            BEGIN:VCARD\r\nFN:B\r\nAGENT:\r\nBEGIN:VCARD\r\nFN:Agent\r\nTEL:999\r\nEND:VCARD\r\nTEL:222\r\nEND:VCARD\r\n\
+            BEGIN:VCARD\r\nFN:Broken\r\n\
+            BEGIN:VCARD\r\nFN:C\r\nEND:VCARD\r\n\
+            BEGIN:VCARD\r\nFN:Unterminated";
+        let mut reader = VcardReader::new(text.as_bytes());
+        let cards: Vec<String> = reader.by_ref().map(|v| v.unwrap()).collect();
+        assert_eq!(3, cards.len());
+        assert_eq!("BEGIN:VCARD\r\nFN:A\r\nNOTE:END:VCARD\r\n end:vcard folded\r\n", cards[0]);
+        let ct = Contact::new(&cards[1]);
+        assert_eq!("B", ct.full_name());
+        assert_eq!(vec!["222"], ct.tel_iter().map(|t| t.number()).collect::<Vec<_>>());
+        assert_eq!("C", Contact::new(&cards[2]).full_name());
+        assert_eq!(
+            vec![
+                MalformedCard { line_no: 1, offset: 0, reason: "Text outside of vcard, ignored" },
+                MalformedCard { line_no: 7, offset: 74, reason: "END:VCARD without BEGIN:VCARD, ignored" },
+                MalformedCard { line_no: 17, offset: 175, reason: "BEGIN:VCARD before END:VCARD, card skipped" },
+                MalformedCard { line_no: 22, offset: 229, reason: "No END:VCARD at end of file, card skipped" },
+            ],
+            reader.malformed()
+        );
+    }
 }
@@ -0,0 +1,157 @@
+use std::fs::metadata;
+use std::thread::sleep;
+use std::time::{Duration, Instant, SystemTime};
+
+pub const WATCH_DEBOUNCE_DEFAULT: u64 = 3;
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Size and modified time of file, None if not exists
+type Stamp = Option<(u64, SystemTime)>;
+
+fn file_stamp(filename: &str) -> Stamp {
+    metadata(filename).ok().map(|m| (m.len(), m.modified().unwrap_or(SystemTime::UNIX_EPOCH)))
+}
+
+/// Polling watcher of files, changes are reported after files are not changed for debounce time
+#[derive(Debug)]
+pub struct Watcher {
+    files: Vec<(String, Stamp)>,
+    debounce: Duration,
+    changed_at: Option<Instant>,
+    changed: Vec<String>,
+    is_retry: bool,
+}
+
+impl Watcher {
+    pub fn new(filenames: &[&str], debounce_secs: u64) -> Self {
+        Self {
+            files: filenames.iter().map(|f| (f.to_string(), file_stamp(f))).collect(),
+            debounce: Duration::from_secs(debounce_secs),
+            changed_at: None,
+            changed: Vec::new(),
+            is_retry: false,
+        }
+    }
+
+    /// Take current file as not changed, for file written by self
+    pub fn reset(&mut self, filename: &str) {
+        for (name, stamp) in self.files.iter_mut().filter(|(name, _)| name == filename) {
+            *stamp = file_stamp(name);
+        }
+        self.changed.retain(|name| name != filename);
+        if self.changed.is_empty() && !self.is_retry {
+            self.changed_at = None;
+        }
+    }
+
+    /// Keep changes of failed cycle pending, they are reported again after debounce time
+    ///
+    /// Empty `changed` retries the start cycle.
+    pub fn retry(&mut self, changed: Vec<String>, now: Instant) {
+        for name in changed {
+            if !self.changed.contains(&name) {
+                self.changed.push(name);
+            }
+        }
+        self.changed_at = Some(now);
+        self.is_retry = true;
+    }
+
+    /// Check files, return changed files if they are settled at `now`
+    pub fn poll(&mut self, now: Instant) -> Option<Vec<String>> {
+        for (name, stamp) in self.files.iter_mut() {
+            let current = file_stamp(name);
+            if current != *stamp {
+                *stamp = current;
+                self.changed_at = Some(now);
+                if !self.changed.contains(name) {
+                    self.changed.push(name.clone());
+                }
+            }
+        }
+        match self.changed_at {
+            Some(at) if now.duration_since(at) >= self.debounce => {
+                self.changed_at = None;
+                self.is_retry = false;
+                Some(std::mem::take(&mut self.changed))
+            },
+            _ => None,
+        }
+    }
+
+    /// Wait until files are changed and settled, return changed files
+    pub fn wait(&mut self) -> Vec<String> {
+        loop {
+            sleep(POLL_INTERVAL);
+            if let Some(changed) = self.poll(Instant::now()) {
+                return changed;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    #[test]
+    fn test_poll() {
+        let dir = env::temp_dir().join(format!("vcf2msip_test_watch_{}", std::process::id()));
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        let vcf = dir.join("contacts.vcf").to_str().unwrap().to_string();
+        let xml = dir.join("Contacts.xml").to_str().unwrap().to_string();
+        write(&vcf, "BEGIN:VCARD").unwrap();
+        let mut watcher = Watcher::new(&[&vcf, &xml], 2);
+        let start = Instant::now();
+        let secs = |n: u64| start + Duration::from_secs(n);
+        assert_eq!(None, watcher.poll(secs(1)));
+        // Changed while writing, reported after settled
+        write(&vcf, "BEGIN:VCARD\r\n").unwrap();
+        assert_eq!(None, watcher.poll(secs(2)));
+        write(&vcf, "BEGIN:VCARD\r\nEND:VCARD\r\n").unwrap();
+        assert_eq!(None, watcher.poll(secs(3)));
+        assert_eq!(None, watcher.poll(secs(4)));
+        assert_eq!(Some(vec![vcf.clone()]), watcher.poll(secs(5)));
+        assert_eq!(None, watcher.poll(secs(8)));
+        // Created file, and file written by self is not reported
+        write(&xml, "<contacts>").unwrap();
+        assert_eq!(None, watcher.poll(secs(9)));
+        watcher.reset(&xml);
+        assert_eq!(None, watcher.poll(secs(12)));
+        write(&xml, "<contacts/>").unwrap();
+        watcher.reset(&xml);
+        assert_eq!(None, watcher.poll(secs(13)));
+        assert_eq!(None, watcher.poll(secs(16)));
+        let _ = remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_retry() {
+        let dir = env::temp_dir().join(format!("vcf2msip_test_watch_retry_{}", std::process::id()));
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        let vcf = dir.join("contacts.vcf").to_str().unwrap().to_string();
+        let xml = dir.join("Contacts.xml").to_str().unwrap().to_string();
+        let mut watcher = Watcher::new(&[&vcf, &xml], 2);
+        let start = Instant::now();
+        let secs = |n: u64| start + Duration::from_secs(n);
+        // Failed start cycle is retried without change
+        watcher.retry(Vec::new(), secs(0));
+        assert_eq!(None, watcher.poll(secs(1)));
+        assert_eq!(Some(Vec::new()), watcher.poll(secs(2)));
+        // Failed cycle keeps change, and is retried until it succeeds
+        write(&vcf, "BEGIN:VCARD\r\nEND:VCARD\r\n").unwrap();
+        assert_eq!(None, watcher.poll(secs(3)));
+        assert_eq!(Some(vec![vcf.clone()]), watcher.poll(secs(5)));
+        watcher.reset(&xml);
+        watcher.retry(vec![vcf.clone()], secs(5));
+        assert_eq!(None, watcher.poll(secs(6)));
+        assert_eq!(Some(vec![vcf.clone()]), watcher.poll(secs(7)));
+        watcher.reset(&xml);
+        assert_eq!(None, watcher.poll(secs(10)));
+        let _ = remove_dir_all(&dir);
+    }
+}
@@ -1,15 +1,19 @@
+use std::collections::HashMap;
 use std::io::BufRead;
 
+use crate::call_log;
 use crate::error_flg;
 use crate::vcf_parser;
 
+use call_log::{is_same_number, number_key, split_ext};
 use error_flg::*;
 use vcf_parser::*;
 
 /// Existing original sip contacts
 #[derive(Debug)]
 pub struct SipContacts {
-    data: Vec<(String, String, String)>, // number(numeric only), number(original), name
+    data: Vec<(String, String, String)>, // number key, number(original), name
+    index: HashMap<String, Vec<usize>>, // number key => indexes of data
 }
 
 impl SipContacts {
@@ -17,65 +21,159 @@ impl SipContacts {
     /// Return empty for no merge mode
     pub fn empty() -> Self {
         let sip_vec: Vec<(String, String, String)> = Vec::new();
-        Self{ data: sip_vec }
+        Self{ data: sip_vec, index: HashMap::new() }
     }
 
     /// Return original sip contact
     pub fn new(filename: &str) -> Result<Self, i32> {
-        let hfile = match File::open(&filename) {
+        let hfile = match File::open(filename) {
             Ok(h) => h,
             _ => return Err(_ERR_FILE_NOT_FOUND),
         };
-        let mut reader = BufReader::new(&hfile);
+        Ok(Self::from_reader(BufReader::new(&hfile)))
+    }
+
+    /// Return original sip contact from reader
+    pub fn from_reader(mut reader: impl BufRead) -> Self {
         let mut sip_vec: Vec<(String, String, String)> = Vec::new();
         let mut buf = String::with_capacity(512);
         let pat = "<contact name=\"(.*)\" number=\"(.*)\" firstname=.*";
-        let re = Regex::new(&pat).unwrap();
+        let re = Regex::new(pat).unwrap();
         loop {
-            let len = match reader.read_line(&mut buf) {
-                Ok(l) => l,
-                Err(_) => 0,
-            };
+            let len = reader.read_line(&mut buf).unwrap_or_default();
             if len == 0 { break; }
             if let Some(c) = re.captures(&buf) {
-                sip_vec.push( (fix_number(&c[2]), c[2].to_string(), c[1].to_string()) );
+                let number = xml_unescape(&c[2]);
+                sip_vec.push( (number_key(&number), number, xml_unescape(&c[1])) );
             }
             buf.clear();
         }
-        Ok( Self{ data: sip_vec } )
+        let mut index: HashMap<String, Vec<usize>> = HashMap::with_capacity(sip_vec.len());
+        for (idx, cts) in sip_vec.iter().enumerate() {
+            index.entry(cts.0.clone()).or_default().push(idx);
+        }
+        Self{ data: sip_vec, index }
     }
 
     /// Data getter
     pub fn data(&self) -> &Vec<(String, String, String)> {
-        &self.data.as_ref()
+        self.data.as_ref()
     }
 
     pub fn is_empty(&self) -> bool {
         self.data.is_empty()
     }
 
+    /// Return indexes of data with same number
+    ///
+    /// SIP URI of other domain, or other extension is not same.
+    fn same_indexes(&self, number: &str) -> Vec<usize> {
+        let Some(idxs) = self.index.get(&number_key(number)) else {
+            return Vec::new();
+        };
+        idxs.iter().copied().filter(|i| {
+            is_same_number(&self.data[*i].1, number) && split_ext(&self.data[*i].1).1 == split_ext(number).1
+        }).collect()
+    }
+
+    /// Return (number, name) of original contacts with same number, for preview
+    pub fn find(&self, number: &str) -> Vec<(&str, &str)> {
+        self.same_indexes(number).into_iter().map(|i| (self.data[i].1.as_str(), self.data[i].2.as_str())).collect()
+    }
+
     /// Clear buffer in sip contact, if phone number exist
-    pub fn clear_exist(&mut self, number: &str) {
-        let mut idx = 0;
-        let mut clr_idx: Vec<usize> = Vec::new();
-        for cts in &self.data {
-            if  cts.0 == fix_number(number) {
-                clr_idx.push(idx);
-            };
-            idx += 1;
+    ///
+    /// Return cleared (number, name) of original contacts.
+    pub fn clear_exist(&mut self, number: &str) -> Vec<(String, String)> {
+        let clr_idx = self.same_indexes(number);
+        if clr_idx.is_empty() {
+            return Vec::new();
         }
+        let key = number_key(number);
+        if let Some(idxs) = self.index.get_mut(&key) {
+            idxs.retain(|i| !clr_idx.contains(i));
+            if idxs.is_empty() {
+                self.index.remove(&key);
+            }
+        }
+        let mut cleared: Vec<(String, String)> = Vec::with_capacity(clr_idx.len());
         for idx in clr_idx {
             self.data[idx].0.clear();
-            self.data[idx].1.clear();
-            self.data[idx].2.clear();
+            cleared.push((
+                std::mem::take(&mut self.data[idx].1),
+                std::mem::take(&mut self.data[idx].2),
+            ));
+        }
+        cleared
+    }
+}
+
+/// Return (attributes, inner XML) of each element in any namespace prefix
+///
+/// Nested element of same name is not supported.
+pub fn xml_elements<'a>(xml: &'a str, name: &str) -> Vec<(&'a str, &'a str)> {
+    let pat = format!(
+        r"(?s)<(?:[A-Za-z][\w.-]*:)?{0}\b([^>]*?)(?:/>|>(.*?)</(?:[A-Za-z][\w.-]*:)?{0}\s*>)",
+        regex::escape(name)
+    );
+    Regex::new(&pat).unwrap().captures_iter(xml)
+        .map(|c| (c.get(1).map_or("", |m| m.as_str()), c.get(2).map_or("", |m| m.as_str())))
+        .collect()
+}
+
+/// Return unescaped text of first element in any namespace prefix
+pub fn xml_tag_text(xml: &str, name: &str) -> Option<String> {
+    xml_elements(xml, name).first().map(|(_, inner)| xml_unescape(inner))
+}
+
+/// Return unescaped value of attribute
+pub fn xml_attr(attrs: &str, name: &str) -> Option<String> {
+    let pat = format!(r#"(?:^|\s){}\s*=\s*(?:"([^"]*)"|'([^']*)')"#, regex::escape(name));
+    let cap = Regex::new(&pat).unwrap().captures(attrs)?;
+    cap.get(1).or(cap.get(2)).map(|m| xml_unescape(m.as_str()))
+}
+
+/// Escape text and attribute value
+pub fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Unescape entities and CDATA
+pub fn xml_unescape(text: &str) -> String {
+    let text = text.trim();
+    if let Some(inner) = text.strip_prefix("<![CDATA[").and_then(|t| t.strip_suffix("]]>")) {
+        return inner.to_string();
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find('&') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        let Some(end) = rest.find(';') else { break; };
+        let entity = &rest[1..end];
+        let c = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X"))
+                .map_or_else(|| entity.strip_prefix('#').and_then(|n| n.parse::<u32>().ok()), |h| u32::from_str_radix(h, 16).ok())
+                .and_then(char::from_u32),
+        };
+        match c {
+            Some(c) => { out.push(c); rest = &rest[end + 1..]; },
+            None => { out.push('&'); rest = &rest[1..]; },
         }
     }
+    out.push_str(rest);
+    out
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    const TEST_XML_FILENAME: &'static str = r".\testfiles\test.xml";
+    const TEST_XML_FILENAME: &str = "testfiles/test.xml";
 
     #[test]
     fn test_xml_parse() {
@@ -84,8 +182,9 @@ mod test {
             ("445566".to_string(), "44-55-66".to_string(), "BBB".to_string()),
             scs.data[1]
         );
-        scs.clear_exist("112233");
+        assert_eq!(vec![("11-22-33".to_string(), "AAA".to_string())], scs.clear_exist("112233"));
         scs.clear_exist("77-88-99");
+        assert!(scs.clear_exist("112233").is_empty());
         assert_eq!(
             vec!(
                 ("".to_string(), "".to_string(), "".to_string()),
@@ -95,4 +194,45 @@ mod test {
             scs.data
         );
     }
+
+    #[test]
+    fn test_sip_contacts() {
+        let xml = "<contacts>\r\n\
+            <contact name=\"Alice\" number=\"sip:alice@pbx.local\" firstname=\"\"/>\r\n\
+            <contact name=\"PBX 201\" number=\"sip:201@pbx.local\" firstname=\"\"/>\r\n\
+            <contact name=\"Other 201\" number=\"sip:201@other.example\" firstname=\"\"/>\r\n\
+            </contacts>\r\n";
+        let mut scs = SipContacts::from_reader(xml.as_bytes());
+        assert_eq!("alice", scs.data[0].0);
+        assert_eq!(vec![("sip:201@pbx.local", "PBX 201")], scs.find("sip:201@pbx.local"));
+        assert_eq!(vec![("sip:201@pbx.local".to_string(), "PBX 201".to_string())], scs.clear_exist("SIP:201@PBX.local"));
+        assert_eq!(vec![("sip:201@other.example".to_string(), "Other 201".to_string())], scs.clear_exist("201"));
+        assert!(scs.clear_exist("sip:alice@other.example").is_empty());
+        assert_eq!(1, scs.clear_exist("sip:Alice@pbx.local").len());
+        // Extension is compared too
+        let xml = "<contact name=\"A\" number=\"03-1234,,205\" firstname=\"\"/>\r\n\
+            <contact name=\"B\" number=\"0312345;ext=206\" firstname=\"\"/>\r\n";
+        let mut scs = SipContacts::from_reader(xml.as_bytes());
+        assert_eq!(vec![("03-1234,,205".to_string(), "A".to_string())], scs.clear_exist("03-1234 ext. 205"));
+        assert!(scs.clear_exist("0312345").is_empty());
+    }
+
+    #[test]
+    fn test_xml_text() {
+        assert_eq!(Some("a&b<c>\r\nd".to_string()), xml_tag_text("<x:v>a&amp;b&lt;c&gt;&#13;&#x0A;d</x:v>", "v"));
+        assert_eq!(Some("".to_string()), xml_tag_text("<resourcetype/>", "resourcetype"));
+        assert_eq!(None, xml_tag_text("<sync-token-x>1</sync-token-x>", "sync-token"));
+        assert_eq!("<&b>", xml_unescape("<![CDATA[<&b>]]>"));
+        assert_eq!("a & b", xml_unescape("a & b"));
+        let units = xml_elements(r#"<Unit Name="A &amp; B" Phone1='100' /><Unit Name="C"></Unit>"#, "Unit");
+        assert_eq!(2, units.len());
+        assert_eq!(Some("A & B".to_string()), xml_attr(units[0].0, "Name"));
+        assert_eq!(Some("100".to_string()), xml_attr(units[0].0, "Phone1"));
+        assert_eq!(None, xml_attr(units[1].0, "Phone1"));
+        // Names are unescaped on reading, escaped on writing
+        let line = Contact::xml_line("Bob & Co \"<1>\"", "1&2", false);
+        assert!(line.starts_with(r#"<contact name="Bob &amp; Co &quot;&lt;1&gt;&quot;" number="1&amp;2" "#));
+        let scs = SipContacts::from_reader(format!("{}\r\n", line).as_bytes());
+        assert_eq!(("12".to_string(), "1&2".to_string(), "Bob & Co \"<1>\"".to_string()), scs.data[0]);
+    }
 }